@@ -0,0 +1,188 @@
+use crate::error::{GpError, Result};
+use crate::scanner::ScannedFile;
+use crate::storage::{BlobUpload, ChunkRef};
+use fastcdc::v2020::StreamCDC;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+/// このサイズ以上のファイルはコンテンツ定義チャンクに分割して保存する
+pub const CHUNKING_THRESHOLD: u64 = 32 * 1024 * 1024;
+
+const MIN_CHUNK_SIZE: u32 = 1024 * 1024;
+const AVG_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+const MAX_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+
+/// アップロードするblobと、新たに作成したチャンク一覧
+#[derive(Debug, Default)]
+pub struct UploadPlan {
+    pub blobs: Vec<BlobUpload>,
+    /// ファイルハッシュ → チャンク一覧
+    pub manifests: HashMap<String, Vec<ChunkRef>>,
+}
+
+/// FastCDCでファイルを分割し、各チャンクのSHA256を計算する
+///
+/// 編集で一部だけ変わったファイルでも、変わっていない領域は同じチャンクになる。
+pub fn chunk_file(path: &Path) -> Result<Vec<ChunkRef>> {
+    let file = File::open(path)?;
+    let chunker = StreamCDC::new(file, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE);
+
+    chunker
+        .map(|chunk| {
+            let chunk =
+                chunk.map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?;
+            Ok(ChunkRef {
+                hash: format!("{:x}", Sha256::digest(&chunk.data)),
+                size: chunk.length as u64,
+            })
+        })
+        .collect()
+}
+
+/// 変更ファイルをアップロードするblobの一覧に変換する
+///
+/// 閾値以上のファイルはチャンク単位のblobになる。`known` にチャンク一覧がある
+/// ファイル（リネームのみ等）は再分割せずにそれを使う。同じハッシュのblobは1つにまとめる。
+pub fn plan_uploads(
+    files: &[ScannedFile],
+    known: &HashMap<String, Vec<ChunkRef>>,
+) -> Result<UploadPlan> {
+    let mut plan = UploadPlan::default();
+    let mut seen = HashSet::new();
+
+    for file in files {
+        if file.size < CHUNKING_THRESHOLD {
+            if seen.insert(file.hash.clone()) {
+                plan.blobs.push(BlobUpload::whole_file(file));
+            }
+            continue;
+        }
+
+        let chunks = match known.get(&file.hash) {
+            Some(chunks) => chunks.clone(),
+            None => chunk_file(&file.absolute_path)?,
+        };
+
+        let mut offset = 0;
+        for chunk in &chunks {
+            if seen.insert(chunk.hash.clone()) {
+                plan.blobs.push(BlobUpload {
+                    hash: chunk.hash.clone(),
+                    path: file.absolute_path.clone(),
+                    offset,
+                    size: chunk.size,
+                });
+            }
+            offset += chunk.size;
+        }
+
+        plan.manifests.insert(file.hash.clone(), chunks);
+    }
+
+    Ok(plan)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// 疑似乱数で圧縮しにくいデータを作る（チャンク境界が内容で決まることを確認するため）
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn scanned(path: PathBuf, size: u64, hash: &str) -> ScannedFile {
+        ScannedFile {
+            relative_path: PathBuf::from(path.file_name().unwrap()),
+            absolute_path: path,
+            size,
+            hash: hash.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_chunks_cover_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("take.wav");
+        let data = noise(20 * 1024 * 1024, 1);
+        std::fs::write(&path, &data).unwrap();
+
+        let chunks = chunk_file(&path).unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(
+            chunks.iter().map(|c| c.size).sum::<u64>(),
+            data.len() as u64
+        );
+        assert!(chunks.iter().all(|c| c.size <= MAX_CHUNK_SIZE as u64));
+    }
+
+    #[test]
+    fn test_local_edit_keeps_most_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let original = noise(24 * 1024 * 1024, 7);
+        let mut edited = original.clone();
+        edited.splice(
+            12 * 1024 * 1024..12 * 1024 * 1024,
+            b"punch-in".iter().copied(),
+        );
+
+        let a = dir.path().join("a.wav");
+        let b = dir.path().join("b.wav");
+        std::fs::write(&a, &original).unwrap();
+        std::fs::write(&b, &edited).unwrap();
+
+        let before: HashSet<_> = chunk_file(&a)
+            .unwrap()
+            .into_iter()
+            .map(|c| c.hash)
+            .collect();
+        let after = chunk_file(&b).unwrap();
+        let changed = after.iter().filter(|c| !before.contains(&c.hash)).count();
+
+        assert!(changed >= 1);
+        assert!(changed < after.len());
+    }
+
+    #[test]
+    fn test_plan_dedups_and_reuses_known_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("song.als");
+        std::fs::write(&small, b"song").unwrap();
+
+        let files = vec![
+            scanned(small.clone(), 4, "s"),
+            scanned(small, 4, "s"),
+            scanned(dir.path().join("stem.wav"), CHUNKING_THRESHOLD, "big"),
+        ];
+        let mut known = HashMap::new();
+        known.insert(
+            "big".to_string(),
+            vec![
+                ChunkRef {
+                    hash: "c1".to_string(),
+                    size: CHUNKING_THRESHOLD / 2,
+                },
+                ChunkRef {
+                    hash: "c1".to_string(),
+                    size: CHUNKING_THRESHOLD / 2,
+                },
+            ],
+        );
+
+        let plan = plan_uploads(&files, &known).unwrap();
+        let hashes: Vec<_> = plan.blobs.iter().map(|b| b.hash.as_str()).collect();
+        assert_eq!(hashes, vec!["s", "c1"]);
+        assert_eq!(plan.manifests.get("big"), known.get("big"));
+    }
+}
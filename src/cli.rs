@@ -21,6 +21,25 @@ pub enum Commands {
         /// ドライラン（実際にはアップロードしない）
         #[arg(long)]
         dry_run: bool,
+
+        /// ハッシュキャッシュを使わず全ファイルを再ハッシュ
+        #[arg(long)]
+        rehash: bool,
+
+        /// リモートの名前またはURL（省略時は既定のリモート）
+        #[arg(long)]
+        remote: Option<String>,
+    },
+
+    /// 最新のスナップショットとの差分だけを取得して作業コピーを更新
+    Pull {
+        /// ローカルの変更を上書きしてでも更新する
+        #[arg(long)]
+        force: bool,
+
+        /// リモートの名前またはURL（省略時は既定のリモート）
+        #[arg(long)]
+        remote: Option<String>,
     },
 
     /// S3上のスナップショット履歴を表示
@@ -28,30 +47,261 @@ pub enum Commands {
         /// プロジェクト名
         project: Option<String>,
 
+        /// 指定したブランチの履歴だけを表示
+        #[arg(short, long)]
+        branch: Option<String>,
+
         /// 表示する件数
         #[arg(short = 'n', long, default_value = "10")]
         limit: usize,
+
+        /// リモートの名前またはURL（省略時は既定のリモート）
+        #[arg(long)]
+        remote: Option<String>,
+    },
+
+    /// ブランチの作成・一覧（名前を省略すると一覧）
+    Branch {
+        /// ブランチ名（例: radio-edit）
+        name: Option<String>,
+
+        /// 分岐元のスナップショット（省略時は作業コピーの元になったスナップショット）
+        snapshot: Option<String>,
+
+        /// ブランチを削除する
+        #[arg(short, long, requires = "name")]
+        delete: bool,
+    },
+
+    /// 作業中のブランチを切り替える
+    Switch {
+        /// ブランチ名
+        branch: String,
+
+        /// 保存されていない変更を .gp/trash に退避して続行する
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// スナップショットにタグを付ける（名前を省略するとタグ一覧）
+    Tag {
+        /// タグ名（例: mix-v3）
+        name: Option<String>,
+
+        /// タグを付けるスナップショット（省略時は作業コピーの元になったスナップショット）
+        snapshot: Option<String>,
+
+        /// タグを削除する
+        #[arg(short, long, requires = "name")]
+        delete: bool,
+
+        /// 既にあるタグを付け替える
+        #[arg(short, long)]
+        force: bool,
+    },
+
+    /// スナップショット間、またはスナップショットと作業コピーの違いを表示
+    Diff {
+        /// 比較元のスナップショット（省略時は作業コピーの元になったスナップショット）
+        from: Option<String>,
+
+        /// 比較先のスナップショット（省略時は作業コピー）
+        to: Option<String>,
+
+        /// 件数と容量の増減だけを表示
+        #[arg(long)]
+        summary: bool,
+
+        /// JSONで出力
+        #[arg(long)]
+        json: bool,
     },
 
     /// 指定した時点の状態に復元
     Checkout {
-        /// スナップショットのタイムスタンプまたはID
+        /// スナップショットのタグ、タイムスタンプまたはID
         snapshot: String,
 
         /// 復元先のディレクトリ
         #[arg(short, long)]
         output: Option<PathBuf>,
+
+        /// プロジェクト名（省略時は復元先、次に現在のディレクトリの設定から決める）
+        #[arg(short, long)]
+        project: Option<String>,
+
+        /// 保存されていない変更を .gp/trash に退避して続行する
+        #[arg(long)]
+        force: bool,
+
+        /// リモートの名前またはURL（省略時は既定のリモート）
+        #[arg(long)]
+        remote: Option<String>,
     },
 
-    /// プロジェクトの初期化
-    Init,
+    /// スナップショットから一部のファイルだけを復元
+    Restore {
+        /// スナップショットのタグ、タイムスタンプまたはID
+        snapshot: String,
+
+        /// 復元するファイル・フォルダ・globパターン（`--` の後に指定）
+        #[arg(required = true)]
+        paths: Vec<String>,
+
+        /// 別名で書き出す（1ファイルだけを復元する場合）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// 保存されていない変更を .gp/trash に退避して続行する
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// プロジェクトの初期化（`.gp/config.toml` を作成）
+    Init {
+        /// リモートでのプロジェクト名（省略時はフォルダ名）
+        #[arg(long)]
+        name: Option<String>,
+
+        /// 接続先（`s3://bucket` または `file:///path`。省略時はユーザー設定）
+        #[arg(long)]
+        remote: Option<String>,
+    },
 
     /// 現在の状態を表示
-    Status,
+    Status {
+        /// ハッシュキャッシュを使わず全ファイルを再ハッシュ
+        #[arg(long)]
+        rehash: bool,
+    },
 
     /// S3からプロジェクトをクローン
     Clone {
         /// プロジェクト名
         project: String,
+
+        /// リモートの名前またはURL（省略時は既定のリモート）
+        #[arg(long)]
+        remote: Option<String>,
+    },
+
+    /// スナップショットのblobをダウンロードして検証（ファイルは書き込まない）
+    Verify {
+        /// スナップショットのタグ、タイムスタンプまたはID（省略時は最新）
+        snapshot: Option<String>,
+    },
+
+    /// リモートのblobを履歴と突き合わせ、欠落・孤立・破損を検出
+    Fsck {
+        /// blobの中身をダウンロードせず、存在だけを確認する
+        #[arg(long)]
+        quick: bool,
+
+        /// 欠落・破損したblobを手元の作業コピーから再アップロードする
+        #[arg(long)]
+        repair: bool,
+    },
+
+    /// どのスナップショットからも参照されていないblobを削除
+    ///
+    /// 削除対象の一覧から削除までの間に始まったプッシュは、消えるblobを使ってしまうことがある。
+    /// 他の人がプッシュしていないときに実行し、必要なら `gp fsck` で確認すること。
+    Gc {
+        /// ドライラン（削除せずに削除できる容量を表示）
+        #[arg(long)]
+        dry_run: bool,
+
+        /// この時間内に更新されたblobは削除しない（進行中のプッシュを保護。プッシュにかかる時間＋1時間より長くする）
+        #[arg(long, default_value = "24")]
+        grace_hours: u64,
+    },
+
+    /// 保持ルール（.gp/config.toml）に従って古いスナップショットを削除
+    Prune {
+        /// ドライラン（削除せずに削除予定のスナップショットと容量を表示）
+        #[arg(long)]
+        dry_run: bool,
+
+        /// この時間内にアップロードされたblobは削除しない（進行中のプッシュを保護）
+        #[arg(long, default_value = "24")]
+        grace_hours: u64,
+    },
+
+    /// プロジェクトをロックし、他の人のプッシュを止める
+    Lock {
+        /// 他の人のロックを解除して取得する
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// プロジェクトのロックを解除
+    Unlock {
+        /// 他の人のロックでも解除する（管理者向け）
+        #[arg(long)]
+        force: bool,
+    },
+
+    /// 暗号化鍵の管理
+    Key {
+        #[command(subcommand)]
+        command: KeyCommands,
+    },
+
+    /// 名前付きリモートの管理
+    Remote {
+        #[command(subcommand)]
+        command: RemoteCommands,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommands {
+    /// 新しい鍵ファイルを作成（以降のプッシュが暗号化される）
+    New {
+        /// 鍵ファイルの保存先（デフォルト: ~/.config/groovepush/key.json）
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// 鍵ファイルをパスフレーズで保護する
+        #[arg(long)]
+        passphrase: bool,
+    },
+
+    /// 新しい鍵に切り替える（古い鍵は既存データの復号用に残す）
+    Rotate {
+        /// 新しいパスフレーズを設定する
+        #[arg(long)]
+        passphrase: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum RemoteCommands {
+    /// リモートを追加
+    Add {
+        /// リモート名（例: studio）
+        name: String,
+
+        /// 接続先（`s3://bucket` または `file:///path`）
+        url: String,
+
+        /// 既定のリモートにする
+        #[arg(long)]
+        default: bool,
+    },
+
+    /// リモートを削除（保存済みのデータは消えない）
+    Remove {
+        /// リモート名
+        name: String,
+    },
+
+    /// リモートの一覧（既定のリモートには `*` を付ける）
+    List,
+
+    /// 既定のリモートを変更
+    Default {
+        /// リモート名
+        name: String,
     },
 }
@@ -0,0 +1,122 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::error::GpError;
+use crate::storage::{History, Storage, DEFAULT_BRANCH};
+use crate::utils::validate_ref_name;
+use crate::workspace::Workspace;
+
+/// ブランチ一覧を表示する。作業中のブランチには `*` を付ける
+pub async fn run_list(storage: &dyn Storage, path: &Path) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let history = load_history(storage, &project_name).await?;
+    let workspace = Workspace::load(&path.join(".gp"))?;
+
+    for branch in history.branch_names() {
+        let marker = if branch == workspace.branch() {
+            "*"
+        } else {
+            " "
+        };
+        match history.branch_head(branch) {
+            Some(head) => println!(
+                "{} {:<20} {} {}",
+                marker,
+                branch,
+                head.id,
+                head.message.as_deref().unwrap_or("")
+            ),
+            None => println!("{} {:<20} (スナップショットなし)", marker, branch),
+        }
+    }
+    Ok(())
+}
+
+/// ブランチを作る。`snapshot` を省略した場合は作業コピーの元になったスナップショットから分岐する
+pub async fn run_create(
+    storage: &dyn Storage,
+    path: &Path,
+    name: &str,
+    snapshot: Option<&str>,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let history = load_history(storage, &project_name).await?;
+
+    validate_ref_name(name)?;
+    if history.has_branch(name) {
+        anyhow::bail!("ブランチ '{}' は既にあります", name);
+    }
+    if history.tags.contains_key(name) {
+        anyhow::bail!("'{}' は既にタグ名として使われています", name);
+    }
+
+    let workspace = Workspace::load(&path.join(".gp"))?;
+    let start = match snapshot {
+        Some(id) => history
+            .find_snapshot(id)
+            .ok_or_else(|| anyhow::anyhow!("スナップショットが見つかりません: {}", id))?,
+        None => workspace
+            .base_snapshot(&history)
+            .or_else(|| history.branch_head(workspace.branch()))
+            .ok_or_else(|| anyhow::anyhow!("スナップショットがありません"))?,
+    };
+    let start_id = start.id.clone();
+
+    storage
+        .update_history(&project_name, &mut |history: &mut History| {
+            if history.has_branch(name) {
+                return Err(GpError::StorageError(format!(
+                    "ブランチ '{}' は既にあります",
+                    name
+                )));
+            }
+            history.set_branch_head(name, &start_id);
+            Ok(())
+        })
+        .await?;
+
+    println!("ブランチを作りました: {}（{} から分岐）", name, start_id);
+    println!("`gp switch {}` で切り替えられます", name);
+    Ok(())
+}
+
+/// ブランチを削除する。スナップショットは履歴に残る
+pub async fn run_delete(storage: &dyn Storage, path: &Path, name: &str) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let history = load_history(storage, &project_name).await?;
+
+    if name == DEFAULT_BRANCH {
+        anyhow::bail!("{} ブランチは削除できません", DEFAULT_BRANCH);
+    }
+    if !history.has_branch(name) {
+        anyhow::bail!("ブランチが見つかりません: {}", name);
+    }
+    if Workspace::load(&path.join(".gp"))?.branch() == name {
+        anyhow::bail!("作業中のブランチは削除できません（先に `gp switch` で切り替えてください）");
+    }
+
+    storage
+        .update_history(&project_name, &mut |history: &mut History| {
+            history.branches.remove(name);
+            Ok(())
+        })
+        .await?;
+
+    println!("ブランチを削除しました: {}", name);
+    Ok(())
+}
+
+async fn load_history(storage: &dyn Storage, project_name: &str) -> Result<History> {
+    storage
+        .get_history(project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))
+}
@@ -1,19 +1,60 @@
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
-use std::fs;
+use chrono::Utc;
+use std::collections::HashMap;
 use std::path::Path;
 
-use crate::storage::{extract_project_name, S3Storage};
+use crate::config::ProjectConfig;
+use crate::scanner::Scanner;
+use crate::storage::{DownloadTarget, Storage};
+use crate::utils::validate_project_name;
+use crate::workspace::{self, CheckoutPlan, Workspace};
 
-pub async fn run(snapshot_id: &str, output: Option<&Path>) -> Result<()> {
+/// 作業コピーをスナップショットの内容に置き換える
+///
+/// プロジェクトは `project`、復元先（`output`、省略時は `path`）の設定、`path` の設定の順に決め、
+/// どれにもプロジェクト名が無ければ `--project` の指定を求める。
+/// 保存されていない変更が上書き・削除される場合は何もせずに中止する。
+/// `force` の場合は、それらのファイルを `.gp/trash/` に退避してから続行する。
+/// 復元先に設定ファイルが無ければ作成し、取り出し元の `remote` を既定のリモートにする。
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    project: Option<&str>,
+    snapshot_id: &str,
+    output: Option<&Path>,
+    remote: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let current_dir = path;
     let path = match output {
-        Some(p) => p.to_path_buf(),
-        None => std::env::current_dir()?,
+        Some(p) => current_dir.join(p),
+        None => current_dir.to_path_buf(),
     };
 
-    let project_name = extract_project_name(&path);
+    let config = ProjectConfig::load(current_dir)?;
+
+    // 別のプロジェクトの作業コピーに上書きしない
+    let output_name = ProjectConfig::load_file(&ProjectConfig::path(&path))?.name;
+    let project_name = match (project, &output_name) {
+        (Some(name), Some(existing)) if name != existing => {
+            anyhow::bail!(
+                "{} はプロジェクト '{}' の作業コピーです（'{}' を復元しようとしました）",
+                path.display(),
+                existing,
+                name
+            );
+        }
+        (Some(name), _) => name.to_string(),
+        (None, Some(existing)) => existing.clone(),
+        (None, None) => config.name.clone().ok_or_else(|| {
+            anyhow::anyhow!(
+                "復元するプロジェクトが分かりません。--project でプロジェクト名を指定してください"
+            )
+        })?,
+    };
+    validate_project_name(&project_name)?;
+    let gp_dir = path.join(".gp");
 
-    let storage = S3Storage::new(None).await?;
     let history = storage
         .get_history(&project_name)
         .await?
@@ -22,41 +63,83 @@ pub async fn run(snapshot_id: &str, output: Option<&Path>) -> Result<()> {
         })?;
 
     let snapshot = history
-        .find_snapshot_by_prefix(snapshot_id)
+        .find_snapshot(snapshot_id)
         .ok_or_else(|| {
             anyhow::anyhow!("スナップショットが見つかりません: {}", snapshot_id)
         })?;
 
+    // 元のスナップショットが分からなければ、最新のスナップショットと比べる
+    let base = Workspace::load(&gp_dir)?
+        .base_snapshot(&history)
+        .or_else(|| history.head_snapshot());
+
+    let local: HashMap<String, String> = if path.exists() {
+        println!("スキャン中...");
+        Scanner::new(&path)?
+            .scan()?
+            .into_iter()
+            .map(|f| (f.relative_path.to_string_lossy().to_string(), f.hash))
+            .collect()
+    } else {
+        HashMap::new()
+    };
+
+    let plan = CheckoutPlan::new(&local, snapshot, base);
+
+    if !plan.unsaved.is_empty() {
+        println!("\n保存されていない変更:");
+        for file in &plan.unsaved {
+            println!("  {}", file);
+        }
+        if !force {
+            anyhow::bail!(
+                "保存されていない変更が失われるため中止しました（`gp push` で先に保存するか、--force で .gp/trash に退避してください）"
+            );
+        }
+
+        let label = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        for file in &plan.unsaved {
+            workspace::move_to_trash(&path, file, &label)?;
+        }
+        println!(
+            "{} 件を退避しました: {}\n",
+            plan.unsaved.len(),
+            gp_dir.join("trash").join(&label).display()
+        );
+    }
+
     println!("復元中: {}", snapshot.id);
     if let Some(msg) = &snapshot.message {
         println!("メッセージ: {}", msg);
     }
     println!("ファイル数: {}\n", snapshot.files.len());
 
-    let pb = ProgressBar::new(snapshot.files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}")
-            .expect("プログレスバーのテンプレートエラー")
-            .progress_chars("#>-"),
-    );
-
-    for (relative_path, hash) in &snapshot.files {
-        let target_path = path.join(relative_path);
+    let targets: Vec<DownloadTarget> = plan
+        .download
+        .iter()
+        .flat_map(|file| DownloadTarget::for_file(snapshot, file, path.join(file)))
+        .collect();
+    storage.download_blobs(&project_name, &targets).await?;
 
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)?;
+    for file in &plan.delete {
+        workspace::remove_file(&path, file)?;
+    }
+    Workspace::record_base(&gp_dir, &snapshot.id)?;
+    if !ProjectConfig::path(&path).exists() {
+        // 取り出し元のリモートを、その作業コピーの既定のリモートにする
+        let mut config = config.for_new_project(&project_name)?;
+        if let Some(remote) = remote {
+            config.remote = Some(remote.to_string());
         }
-
-        let data = storage.download_blob(&project_name, hash).await?;
-        fs::write(&target_path, data)?;
-
-        pb.inc(1);
+        config.save(&path)?;
     }
 
-    pb.finish_with_message("復元完了");
-
     println!("\n復元完了: {}", snapshot.id);
+    println!(
+        "更新 {} 件、削除 {} 件",
+        plan.download.len(),
+        plan.delete.len()
+    );
     println!("ディレクトリ: {}", path.display());
 
     Ok(())
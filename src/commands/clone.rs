@@ -1,12 +1,18 @@
 use anyhow::Result;
-use indicatif::{ProgressBar, ProgressStyle};
 use std::fs;
 use std::path::Path;
 
-use crate::storage::S3Storage;
+use crate::config::ProjectConfig;
+use crate::storage::{DownloadTarget, Storage};
 use crate::utils::validate_project_name;
-
-pub async fn run(project_name: &str, current_dir: &Path) -> Result<()> {
+use crate::workspace::Workspace;
+
+pub async fn run(
+    storage: &dyn Storage,
+    project_name: &str,
+    current_dir: &Path,
+    remote: Option<&str>,
+) -> Result<()> {
     validate_project_name(project_name)?;
 
     let target_dir = current_dir.join(project_name);
@@ -15,15 +21,14 @@ pub async fn run(project_name: &str, current_dir: &Path) -> Result<()> {
         anyhow::bail!("ディレクトリが既に存在します: {}", target_dir.display());
     }
 
-    let storage = S3Storage::new(None).await?;
     let history = storage
         .get_history(project_name)
         .await?
         .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' が見つかりません", project_name))?;
 
+    // 他のブランチに新しいスナップショットがあっても、main の最新を取り出す
     let snapshot = history
-        .snapshots
-        .last()
+        .head_snapshot()
         .ok_or_else(|| anyhow::anyhow!("スナップショットがありません"))?;
 
     println!("クローン中: {}", project_name);
@@ -35,31 +40,18 @@ pub async fn run(project_name: &str, current_dir: &Path) -> Result<()> {
 
     fs::create_dir_all(&target_dir)?;
 
-    let pb = ProgressBar::new(snapshot.files.len() as u64);
-    pb.set_style(
-        ProgressStyle::default_bar()
-            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len}")
-            .expect("プログレスバーのテンプレートエラー")
-            .progress_chars("#>-"),
-    );
-
-    for (relative_path, hash) in &snapshot.files {
-        let target_path = target_dir.join(relative_path);
-
-        if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)?;
-        }
-
-        let data = storage.download_blob(project_name, hash).await?;
-        fs::write(&target_path, data)?;
-
-        pb.inc(1);
-    }
-
-    pb.finish_with_message("クローン完了");
+    let targets = DownloadTarget::for_snapshot(snapshot, &target_dir);
+    storage.download_blobs(project_name, &targets).await?;
 
     let gp_dir = target_dir.join(".gp");
     fs::create_dir_all(&gp_dir)?;
+    Workspace::record_base(&gp_dir, &snapshot.id)?;
+    // クローン元のリモートを、その作業コピーの既定のリモートにする
+    let mut config = ProjectConfig::load(current_dir)?.for_new_project(project_name)?;
+    if let Some(remote) = remote {
+        config.remote = Some(remote.to_string());
+    }
+    config.save(&target_dir)?;
 
     println!("\nクローン完了: {}", target_dir.display());
 
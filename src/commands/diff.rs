@@ -0,0 +1,153 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::scanner::{compare_files, file_entries, FileChange, Scanner};
+use crate::storage::{History, Snapshot, Storage};
+use crate::utils::format_size;
+use crate::workspace::Workspace;
+
+/// `--json` で出力する比較結果
+#[derive(Serialize)]
+struct DiffReport<'a> {
+    from: &'a str,
+    /// 作業コピーと比較した場合は `null`
+    to: Option<&'a str>,
+    summary: DiffSummary,
+    changes: &'a [FileChange],
+}
+
+#[derive(Default, Serialize)]
+struct DiffSummary {
+    added: usize,
+    removed: usize,
+    modified: usize,
+    renamed: usize,
+    size_delta: i64,
+}
+
+impl DiffSummary {
+    fn new(changes: &[FileChange]) -> Self {
+        let mut summary = Self::default();
+        for change in changes {
+            match change {
+                FileChange::Added { .. } => summary.added += 1,
+                FileChange::Removed { .. } => summary.removed += 1,
+                FileChange::Modified { .. } => summary.modified += 1,
+                FileChange::Renamed { .. } => summary.renamed += 1,
+            }
+            summary.size_delta += change.size_delta();
+        }
+        summary
+    }
+}
+
+/// 2つのスナップショット、またはスナップショットと作業コピーの違いを表示する
+///
+/// `from` を省略した場合は作業コピーの元になったスナップショット（不明なら最新）と、
+/// `to` を省略した場合は作業コピーと比較する。
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    from: Option<&str>,
+    to: Option<&str>,
+    summary_only: bool,
+    json: bool,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    let from_snapshot = match from {
+        Some(id) => find_snapshot(&history, id)?,
+        None => Workspace::load(&path.join(".gp"))?
+            .base_snapshot(&history)
+            .or_else(|| history.head_snapshot())
+            .ok_or_else(|| anyhow::anyhow!("スナップショットがありません"))?,
+    };
+    let to_snapshot = to.map(|id| find_snapshot(&history, id)).transpose()?;
+
+    let new_files = match to_snapshot {
+        Some(snapshot) => snapshot.file_entries(),
+        None => {
+            if !json {
+                println!("スキャン中...");
+            }
+            file_entries(&Scanner::new(&path)?.scan()?)
+        }
+    };
+    let changes = compare_files(&from_snapshot.file_entries(), &new_files);
+    let summary = DiffSummary::new(&changes);
+
+    if json {
+        let report = DiffReport {
+            from: &from_snapshot.id,
+            to: to_snapshot.map(|s| s.id.as_str()),
+            summary,
+            changes: &changes,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    println!(
+        "比較: {} → {}",
+        from_snapshot.id,
+        to_snapshot.map_or("作業コピー", |s| s.id.as_str())
+    );
+
+    if changes.is_empty() {
+        println!("違いはありません");
+        return Ok(());
+    }
+
+    if !summary_only {
+        println!();
+        for change in &changes {
+            match change {
+                FileChange::Added { path, .. } => {
+                    println!("  追加: {} ({})", path, format_delta(change.size_delta()))
+                }
+                FileChange::Removed { path, .. } => {
+                    println!("  削除: {} ({})", path, format_delta(change.size_delta()))
+                }
+                FileChange::Modified { path, .. } => {
+                    println!("  変更: {} ({})", path, format_delta(change.size_delta()))
+                }
+                FileChange::Renamed { from, to, .. } => {
+                    println!("  名前変更: {} → {}", from, to)
+                }
+            }
+        }
+    }
+
+    println!(
+        "\n追加 {} 件、削除 {} 件、変更 {} 件、名前変更 {} 件（{}）",
+        summary.added,
+        summary.removed,
+        summary.modified,
+        summary.renamed,
+        format_delta(summary.size_delta)
+    );
+
+    Ok(())
+}
+
+fn find_snapshot<'a>(history: &'a History, id: &str) -> Result<&'a Snapshot> {
+    history
+        .find_snapshot(id)
+        .ok_or_else(|| anyhow::anyhow!("スナップショットが見つかりません: {}", id))
+}
+
+/// 容量の増減を符号付きで表示する
+fn format_delta(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, format_size(delta.unsigned_abs()))
+}
@@ -0,0 +1,238 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+
+use crate::chunker::plan_uploads;
+use crate::config::ProjectConfig;
+use crate::error::GpError;
+use crate::scanner::Scanner;
+use crate::storage::codec::Codec;
+use crate::storage::{BlobEncoding, History, Storage, StoredBlob, UploadJournal};
+use crate::utils::format_size;
+
+/// リモートのblob一覧を履歴・current_state.jsonと突き合わせて整合性を検査する
+///
+/// `quick` ならblobの中身はダウンロードせず、存在だけを確認する。
+/// `repair` なら欠落・破損したblobを手元の作業コピーから再アップロードする。
+pub async fn run(storage: &dyn Storage, path: &Path, quick: bool, repair: bool) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .unwrap_or_else(|| History::new(&project_name));
+    let state = storage.get_remote_state(&project_name).await?;
+
+    println!("検査中: {}", storage.location(&project_name));
+
+    let mut referenced = history.referenced_blobs();
+    let manifests = history.chunk_manifests();
+
+    // current_state.jsonのファイルは、チャンク一覧が無ければ丸ごと1つのblobのはず
+    for hash in state.values() {
+        if !manifests.contains_key(hash) {
+            referenced.entry(hash.clone()).or_insert(0);
+        }
+    }
+
+    let stored: HashMap<String, StoredBlob> = storage
+        .list_blobs(&project_name)
+        .await?
+        .into_iter()
+        .map(|b| (b.hash.clone(), b))
+        .collect();
+
+    println!("スナップショット数: {}", history.snapshots.len());
+    println!("参照されているblob: {} 件", referenced.len());
+    println!("リモートのblob: {} 件\n", stored.len());
+
+    let missing: BTreeSet<String> = referenced
+        .keys()
+        .filter(|hash| !stored.contains_key(*hash))
+        .cloned()
+        .collect();
+
+    let mut orphaned: Vec<&StoredBlob> = stored
+        .values()
+        .filter(|b| !referenced.contains_key(&b.hash))
+        .collect();
+    orphaned.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let mut corrupt = BTreeSet::new();
+    let mut errors = Vec::new();
+    if !quick {
+        let present: BTreeMap<String, u64> = referenced
+            .iter()
+            .filter(|(hash, _)| stored.contains_key(*hash))
+            .map(|(hash, &size)| (hash.clone(), size))
+            .collect();
+
+        for (hash, error) in storage.verify_blobs(&project_name, &present).await {
+            match error {
+                GpError::HashMismatch { .. } | GpError::CorruptBlob(_) => {
+                    corrupt.insert(hash);
+                }
+                other => errors.push((hash, other)),
+            }
+        }
+    }
+
+    let owners = blob_owners(&history, &state);
+    print_blobs("欠落", &missing, &owners);
+    print_blobs("破損", &corrupt, &owners);
+    for (hash, error) in &errors {
+        println!("エラー: {} ({})", hash, error);
+    }
+
+    if !orphaned.is_empty() {
+        let total: u64 = orphaned.iter().map(|b| b.size).sum();
+        println!(
+            "孤立: {} 件 ({}) - どのスナップショットからも参照されていません",
+            orphaned.len(),
+            format_size(total)
+        );
+        for blob in &orphaned {
+            match blob.last_modified {
+                Some(modified) => println!(
+                    "  {} ({}, {})",
+                    blob.hash,
+                    format_size(blob.size),
+                    modified.format("%Y-%m-%d %H:%M UTC")
+                ),
+                None => println!("  {} ({})", blob.hash, format_size(blob.size)),
+            }
+        }
+    }
+
+    let mut broken: BTreeSet<String> = missing.union(&corrupt).cloned().collect();
+
+    if repair && !broken.is_empty() {
+        let repaired = reupload(
+            storage,
+            &path,
+            &project_name,
+            config.codec()?,
+            &history,
+            &broken,
+            &corrupt,
+        )
+        .await?;
+        println!("\n再アップロード: {} 件", repaired.len());
+        for hash in &repaired {
+            broken.remove(hash);
+        }
+    }
+
+    if broken.is_empty() && errors.is_empty() {
+        if quick {
+            println!("\n検査完了: 欠落しているblobはありません（内容は検証していません）");
+        } else {
+            println!("\n検査完了: 欠落・破損しているblobはありません");
+        }
+        return Ok(());
+    }
+
+    if !repair && !broken.is_empty() {
+        println!(
+            "\n手元の作業コピーに内容が残っていれば `gp fsck --repair` で再アップロードできます"
+        );
+    }
+
+    anyhow::bail!("{} 件のblobに問題があります", broken.len() + errors.len())
+}
+
+/// blob → そのblobを参照しているファイルパス
+fn blob_owners(
+    history: &History,
+    state: &HashMap<String, String>,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let mut owners: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for snapshot in &history.snapshots {
+        for (path, hash) in &snapshot.files {
+            let hashes = match snapshot.chunks.get(hash) {
+                Some(chunks) => chunks.iter().map(|c| c.hash.clone()).collect(),
+                None => vec![hash.clone()],
+            };
+            for hash in hashes {
+                owners.entry(hash).or_default().insert(path.clone());
+            }
+        }
+    }
+    for (path, hash) in state {
+        owners.entry(hash.clone()).or_default().insert(path.clone());
+    }
+    owners
+}
+
+fn print_blobs(
+    label: &str,
+    hashes: &BTreeSet<String>,
+    owners: &BTreeMap<String, BTreeSet<String>>,
+) {
+    for hash in hashes {
+        println!("{}: {}", label, hash);
+        for file in owners.get(hash).into_iter().flatten() {
+            println!("  {}", file);
+        }
+    }
+}
+
+/// 欠落・破損したblobを作業コピーのファイルから作り直してアップロードし、復旧できたblobを返す
+async fn reupload(
+    storage: &dyn Storage,
+    path: &Path,
+    project_name: &str,
+    codec: Codec,
+    history: &History,
+    broken: &BTreeSet<String>,
+    corrupt: &BTreeSet<String>,
+) -> Result<BTreeSet<String>> {
+    println!("\n作業コピーをスキャン中...");
+    let manifests = history.chunk_manifests();
+
+    // 必要な内容を含むファイルだけを対象にする
+    let candidates: Vec<_> = Scanner::new(path)?
+        .scan()?
+        .into_iter()
+        .filter(|f| {
+            broken.contains(&f.hash)
+                || manifests
+                    .get(&f.hash)
+                    .is_some_and(|chunks| chunks.iter().any(|c| broken.contains(&c.hash)))
+        })
+        .collect();
+
+    let blobs: Vec<_> = plan_uploads(&candidates, &manifests)?
+        .blobs
+        .into_iter()
+        .filter(|b| broken.contains(&b.hash))
+        .collect();
+    if blobs.is_empty() {
+        return Ok(BTreeSet::new());
+    }
+
+    // 破損したblobは上書きできるよう先に削除する（アップロードは既存のblobを飛ばすため）
+    let replace: Vec<String> = blobs
+        .iter()
+        .filter(|b| corrupt.contains(&b.hash))
+        .map(|b| b.hash.clone())
+        .collect();
+    storage.delete_blobs(project_name, &replace).await?;
+
+    let journal = UploadJournal::in_memory();
+    let staging_dir = path.join(".gp").join("staging");
+    let repaired = blobs.iter().map(|b| b.hash.clone()).collect();
+    let encoding = BlobEncoding::new(codec, storage.keyring(), &staging_dir);
+
+    let result = storage
+        .upload_blobs(project_name, &blobs, &encoding, &journal)
+        .await;
+    // 他のプッシュがエンコード中であれば消さない
+    let _ = fs::remove_dir(&staging_dir);
+    result?;
+
+    Ok(repaired)
+}
@@ -0,0 +1,103 @@
+use anyhow::Result;
+use chrono::{Duration, Utc};
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::storage::{History, Storage, StoredBlob};
+use crate::utils::format_size;
+
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    dry_run: bool,
+    grace_hours: u64,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    println!("プロジェクト: {}", project_name);
+    collect_garbage(storage, &history, dry_run, grace_hours).await
+}
+
+/// どのスナップショットからも参照されていないblobを削除する
+///
+/// 進行中のプッシュがアップロードしたばかりのblobはまだ履歴に載っていないため、
+/// 最終更新から `grace_hours` 時間以内のblobは削除しない。プッシュが再利用する古いblobは
+/// 送り直されて最終更新が新しくなるが、一覧から削除までの間に始まったプッシュとは競合しうる。
+pub async fn collect_garbage(
+    storage: &dyn Storage,
+    history: &History,
+    dry_run: bool,
+    grace_hours: u64,
+) -> Result<()> {
+    let project_name = &history.project_name;
+    let reachable = history.referenced_blobs();
+    let state: HashSet<String> = storage
+        .get_remote_state(project_name)
+        .await?
+        .into_values()
+        .collect();
+    let cutoff = Utc::now() - Duration::hours(grace_hours as i64);
+
+    let mut garbage: Vec<StoredBlob> = Vec::new();
+    let mut recent = 0;
+    for blob in storage.list_blobs(project_name).await? {
+        // current_state.jsonだけが参照するblobも、直後に履歴へ載る途中の可能性があるので残す
+        if reachable.contains_key(&blob.hash) || state.contains(&blob.hash) {
+            continue;
+        }
+        // 更新時刻が分からないblobは新しいものとみなす
+        if blob.last_modified.is_none_or(|modified| modified > cutoff) {
+            recent += 1;
+            continue;
+        }
+        garbage.push(blob);
+    }
+    garbage.sort_by(|a, b| a.hash.cmp(&b.hash));
+
+    let total: u64 = garbage.iter().map(|b| b.size).sum();
+
+    if recent > 0 {
+        println!(
+            "猶予期間（{} 時間）内のため保持: {} 件",
+            grace_hours, recent
+        );
+    }
+
+    if garbage.is_empty() {
+        println!("削除できるblobはありません");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("\n[ドライラン] 削除予定のblob:");
+        for blob in &garbage {
+            println!("  {} ({})", blob.hash, format_size(blob.size));
+        }
+        println!(
+            "\n削除できる容量: {} ({} 件)",
+            format_size(total),
+            garbage.len()
+        );
+        return Ok(());
+    }
+
+    let hashes: Vec<String> = garbage.into_iter().map(|b| b.hash).collect();
+    storage.delete_blobs(project_name, &hashes).await?;
+
+    println!(
+        "blobを削除しました: {} 件 ({})",
+        hashes.len(),
+        format_size(total)
+    );
+
+    Ok(())
+}
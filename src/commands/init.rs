@@ -2,12 +2,47 @@ use anyhow::Result;
 use std::fs;
 use std::path::Path;
 
-pub fn run(path: &Path) -> Result<()> {
+use crate::config::ProjectConfig;
+use crate::utils::validate_project_name;
+
+/// `.gp/` と `.gp-ignore` を作り、`.gp/config.toml` にプロジェクト設定を書き出す
+///
+/// `name`・`remote` を省略した場合はフォルダ名とユーザー設定（または環境変数）の値を使う。
+/// 既に `.gp/config.toml` がある場合は書き換えない。
+pub fn run(path: &Path, name: Option<&str>, remote: Option<&str>) -> Result<()> {
     let gp_dir = path.join(".gp");
     if !gp_dir.exists() {
         fs::create_dir_all(&gp_dir)?;
     }
 
+    let config_file = ProjectConfig::path(path);
+    if config_file.exists() {
+        println!("設定ファイルは既にあります: {}", config_file.display());
+    } else {
+        let config = ProjectConfig::load(path)?;
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => config.project_name(path),
+        };
+        validate_project_name(&name)?;
+
+        let mut config = config.for_new_project(&name)?;
+        if let Some(remote) = remote {
+            config.remote(Some(remote))?;
+            config.remote = Some(remote.to_string());
+        }
+        config.save(path)?;
+        println!("設定ファイルを作成しました: {}", config_file.display());
+        println!("プロジェクト名: {}", name);
+        match &config.remote {
+            Some(remote) => println!("リモート: {}", remote),
+            None => println!(
+                "警告: リモートが設定されていません（{} の remote を指定してください）",
+                config_file.display()
+            ),
+        }
+    }
+
     let ignore_file = path.join(".gp-ignore");
     if !ignore_file.exists() {
         let default_content = r#"# GroovePush 除外設定
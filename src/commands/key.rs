@@ -0,0 +1,70 @@
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+use crate::crypto::{read_new_passphrase, read_passphrase, Keyring};
+
+fn key_path(output: Option<&Path>) -> Result<PathBuf> {
+    match output {
+        Some(path) => Ok(path.to_path_buf()),
+        None => Keyring::default_path()
+            .ok_or_else(|| anyhow::anyhow!("鍵ファイルの場所を決められません（HOMEが未設定です）")),
+    }
+}
+
+pub fn run_new(output: Option<&Path>, passphrase: bool) -> Result<()> {
+    let path = key_path(output)?;
+    if path.exists() {
+        anyhow::bail!(
+            "鍵ファイルが既に存在します: {}\n鍵を入れ替える場合は 'gp key rotate' を使ってください",
+            path.display()
+        );
+    }
+
+    let passphrase = if passphrase {
+        Some(read_new_passphrase()?)
+    } else {
+        None
+    };
+
+    let keyring = Keyring::generate();
+    keyring.save(&path, passphrase.as_deref())?;
+
+    println!("鍵を作成しました: {}", path.display());
+    println!("鍵ID: {}", keyring.active().id_hex());
+    println!("\nこの鍵ファイルを失うと、暗号化したプロジェクトは復元できません。");
+    println!("安全な場所にバックアップしてください。同じ鍵ファイルをチームで共有できます。");
+    Ok(())
+}
+
+pub fn run_rotate(passphrase: bool) -> Result<()> {
+    let path = key_path(None)?;
+    if !path.exists() {
+        anyhow::bail!(
+            "鍵ファイルがありません: {}\nまず 'gp key new' で作成してください",
+            path.display()
+        );
+    }
+
+    let current = if Keyring::is_protected(&path)? {
+        Some(read_passphrase("現在のパスフレーズ: ")?)
+    } else {
+        None
+    };
+    let mut keyring = Keyring::load(&path, current.as_deref())?;
+    keyring.rotate();
+
+    let passphrase = if passphrase {
+        Some(read_new_passphrase()?)
+    } else {
+        current
+    };
+    keyring.save(&path, passphrase.as_deref())?;
+
+    println!("新しい鍵に切り替えました: {}", keyring.active().id_hex());
+    println!(
+        "以降のプッシュは新しい鍵で暗号化されます（保持している鍵: {} 個）",
+        keyring.key_count()
+    );
+    println!("既存のblobを復号するため、古い鍵も鍵ファイルに残しています。");
+    Ok(())
+}
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::error::GpError;
+use crate::storage::{ProjectLock, Storage};
+
+/// プロジェクトをロックする。`force` なら他の人のロックを自分のロックに置き換える
+pub async fn run_lock(storage: &dyn Storage, path: &Path, force: bool) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let lock = ProjectLock::current();
+    let created = match storage.get_lock(&project_name).await? {
+        Some(current) if current.is_mine() => {
+            println!("既にロックしています: {}", current);
+            return Ok(());
+        }
+        Some(current) if !force => {
+            anyhow::bail!(
+                "{} がプロジェクトをロックしています（--force で強制的に取得できます）",
+                current
+            );
+        }
+        // 解除してから取り直すと、その間に他の人が取ったロックを消してしまうため、置き換える
+        Some(current) => {
+            println!("{} のロックを解除します", current);
+            storage.replace_lock(&project_name, &current, &lock).await
+        }
+        None => storage.create_lock(&project_name, &lock).await,
+    };
+    match created {
+        Ok(()) => {}
+        Err(GpError::ProjectLocked(holder)) => {
+            anyhow::bail!("先に {} がプロジェクトをロックしました", holder)
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    println!("ロックしました: {}", lock);
+    println!("作業が終わったら `gp unlock` で解除してください");
+    Ok(())
+}
+
+/// ロックを解除する。他の人のロックは `force` の場合だけ解除する
+pub async fn run_unlock(storage: &dyn Storage, path: &Path, force: bool) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let Some(lock) = storage.get_lock(&project_name).await? else {
+        println!("ロックされていません");
+        return Ok(());
+    };
+
+    if !lock.is_mine() && !force {
+        anyhow::bail!("{} のロックです（--force で強制的に解除できます）", lock);
+    }
+
+    storage.delete_lock(&project_name).await?;
+
+    if lock.is_mine() {
+        println!("ロックを解除しました");
+    } else {
+        println!("{} のロックを強制的に解除しました", lock);
+    }
+    Ok(())
+}
+
+/// 他の人が持っているロック。自分のロックやロックが無い場合は `None`
+pub async fn held_by_other(
+    storage: &dyn Storage,
+    project_name: &str,
+) -> Result<Option<ProjectLock>> {
+    Ok(storage
+        .get_lock(project_name)
+        .await?
+        .filter(|lock| !lock.is_mine()))
+}
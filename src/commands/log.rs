@@ -1,19 +1,25 @@
 use anyhow::Result;
 use std::fs;
 
-use crate::storage::{extract_project_name, S3Storage};
+use crate::config::ProjectConfig;
+use crate::storage::{Snapshot, Storage};
 use crate::utils::format_size;
 
-pub async fn run(project: Option<&str>, limit: usize) -> Result<()> {
+/// 履歴を新しい順に表示する。`branch` を指定した場合はその先頭から親をたどった分だけを表示する
+pub async fn run(
+    storage: &dyn Storage,
+    project: Option<&str>,
+    branch: Option<&str>,
+    limit: usize,
+) -> Result<()> {
     let project_name = match project {
         Some(p) => p.to_string(),
         None => {
             let path = fs::canonicalize(".")?;
-            extract_project_name(&path)
+            ProjectConfig::load(&path)?.project_name(&path)
         }
     };
 
-    let storage = S3Storage::new(None).await?;
     let history = storage.get_history(&project_name).await?;
 
     match history {
@@ -29,9 +35,34 @@ pub async fn run(project: Option<&str>, limit: usize) -> Result<()> {
                 return Ok(());
             }
 
-            let total = h.snapshots.len();
-            for snapshot in h.snapshots.iter().rev().take(limit) {
-                println!("snapshot {}", snapshot.id);
+            let snapshots: Vec<&Snapshot> = match branch {
+                Some(branch) => {
+                    let head = h
+                        .branch_head_id(branch)
+                        .ok_or_else(|| anyhow::anyhow!("ブランチが見つかりません: {}", branch))?;
+                    println!("ブランチ: {}\n", branch);
+                    h.ancestors(head)
+                }
+                None => h.snapshots.iter().rev().collect(),
+            };
+
+            let total = snapshots.len();
+            for snapshot in snapshots.into_iter().take(limit) {
+                let labels: Vec<String> = h
+                    .branches_of(&snapshot.id)
+                    .into_iter()
+                    .map(String::from)
+                    .chain(
+                        h.tags_of(&snapshot.id)
+                            .into_iter()
+                            .map(|t| format!("tag: {}", t)),
+                    )
+                    .collect();
+                if labels.is_empty() {
+                    println!("snapshot {}", snapshot.id);
+                } else {
+                    println!("snapshot {} ({})", snapshot.id, labels.join(", "));
+                }
                 if let Some(msg) = &snapshot.message {
                     println!("メッセージ: {}", msg);
                 }
@@ -1,6 +1,19 @@
+pub mod branch;
 pub mod checkout;
 pub mod clone;
+pub mod diff;
+pub mod fsck;
+pub mod gc;
 pub mod init;
+pub mod key;
+pub mod lock;
 pub mod log;
+pub mod prune;
+pub mod pull;
 pub mod push;
+pub mod remote;
+pub mod restore;
 pub mod status;
+pub mod switch;
+pub mod tag;
+pub mod verify;
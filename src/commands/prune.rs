@@ -0,0 +1,94 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use crate::commands::gc;
+use crate::config::{ProjectConfig, RetentionPolicy};
+use crate::storage::{History, Snapshot, Storage};
+
+/// 保持ルールに当てはまらないスナップショットを履歴から削除し、不要になったblobを片付ける
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    dry_run: bool,
+    grace_hours: u64,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let policy = config.retention.ok_or_else(|| {
+        anyhow::anyhow!(
+            "保持ルールが設定されていません（{} の [retention]）",
+            ProjectConfig::path(&path).display()
+        )
+    })?;
+
+    if storage.get_history(&project_name).await?.is_none() {
+        anyhow::bail!("プロジェクト '{}' の履歴が見つかりません", project_name);
+    }
+
+    println!("プロジェクト: {}", project_name);
+
+    // 競合して読み直した場合も、最新の履歴に対してルールを適用し直す
+    let mut removed = Vec::new();
+    let mut apply = |history: &mut History| {
+        removed = prune_history(history, &policy);
+        Ok(())
+    };
+    let history = if dry_run {
+        let mut history = storage
+            .get_history(&project_name)
+            .await?
+            .unwrap_or_else(|| History::new(&project_name));
+        apply(&mut history)?;
+        history
+    } else {
+        storage.update_history(&project_name, &mut apply).await?
+    };
+
+    if removed.is_empty() {
+        println!("削除するスナップショットはありません");
+        return Ok(());
+    }
+
+    if dry_run {
+        println!("\n[ドライラン] 削除予定のスナップショット:");
+    } else {
+        println!("\n削除したスナップショット:");
+    }
+    for snapshot in &removed {
+        match &snapshot.message {
+            Some(msg) => println!("  {} {}", snapshot.id, msg),
+            None => println!("  {}", snapshot.id),
+        }
+    }
+    println!(
+        "\n残すスナップショット: {} 件 / 削除: {} 件",
+        history.snapshots.len(),
+        removed.len()
+    );
+
+    gc::collect_garbage(storage, &history, dry_run, grace_hours).await
+}
+
+/// 保持ルールに当てはまらないスナップショットを取り除き、取り除いたスナップショットを返す
+fn prune_history(history: &mut History, policy: &RetentionPolicy) -> Vec<Snapshot> {
+    let mut keep = policy.retained(&history.snapshots, Utc::now());
+    // 各ブランチの最新のスナップショットとタグの付いたスナップショットはルールに関係なく残す
+    keep.extend(history.head.clone());
+    keep.extend(history.branches.values().cloned());
+    keep.extend(history.tags.values().cloned());
+
+    let removed: Vec<Snapshot> = history
+        .snapshots
+        .iter()
+        .filter(|s| !keep.contains(&s.id))
+        .cloned()
+        .collect();
+    let ids: HashSet<String> = removed.iter().map(|s| s.id.clone()).collect();
+    history.remove_snapshots(&ids);
+    removed
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::scanner::Scanner;
+use crate::storage::{DownloadTarget, Storage, DEFAULT_BRANCH};
+use crate::workspace::{self, PullPlan, Workspace};
+
+/// 作業中のブランチの最新スナップショットとの差分だけをダウンロード・削除して作業コピーを更新する
+///
+/// ローカルでも編集されたファイルが上書きされる場合は、`force` でない限り何もせずに中止する。
+pub async fn run(storage: &dyn Storage, path: &Path, force: bool) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let gp_dir = path.join(".gp");
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    // 元のスナップショットが削除（prune）されていたら、元が分からないものとして扱う
+    let workspace = Workspace::load(&gp_dir)?;
+    let base = workspace.base_snapshot(&history);
+    let head = history
+        .branch_head(workspace.branch())
+        .ok_or_else(|| anyhow::anyhow!("ブランチ '{}' が見つかりません", workspace.branch()))?;
+
+    println!("プロジェクト: {}", project_name);
+    if workspace.branch() != DEFAULT_BRANCH {
+        println!("ブランチ: {}", workspace.branch());
+    }
+    println!("最新のスナップショット: {}", head.id);
+    println!("スキャン中...");
+
+    let local: HashMap<String, String> = Scanner::new(&path)?
+        .scan()?
+        .into_iter()
+        .map(|f| (f.relative_path.to_string_lossy().to_string(), f.hash))
+        .collect();
+
+    let mut plan = PullPlan::new(&local, head, base);
+
+    if !plan.conflicts.is_empty() {
+        if !force {
+            println!("\nローカルでも変更されているファイル:");
+            for file in &plan.conflicts {
+                println!("  {}", file);
+            }
+            anyhow::bail!(
+                "ローカルの変更が上書きされるため中止しました（`gp push` で先に保存するか、--force で上書きしてください）"
+            );
+        }
+        println!(
+            "\n{} 件のローカルの変更を上書きします",
+            plan.conflicts.len()
+        );
+        plan.force(head);
+    }
+
+    if plan.is_empty() {
+        Workspace::record_base(&gp_dir, &head.id)?;
+        println!("既に最新です");
+        return Ok(());
+    }
+
+    let targets: Vec<DownloadTarget> = plan
+        .download
+        .iter()
+        .flat_map(|file| DownloadTarget::for_file(head, file, path.join(file)))
+        .collect();
+    if !targets.is_empty() {
+        println!("ダウンロード中...");
+        storage.download_blobs(&project_name, &targets).await?;
+    }
+
+    for file in &plan.delete {
+        workspace::remove_file(&path, file)?;
+    }
+
+    Workspace::record_base(&gp_dir, &head.id)?;
+
+    for file in &plan.download {
+        println!("  更新: {}", file);
+    }
+    for file in &plan.delete {
+        println!("  削除: {}", file);
+    }
+    println!(
+        "\nプル完了: 更新 {} 件、削除 {} 件",
+        plan.download.len(),
+        plan.delete.len()
+    );
+
+    Ok(())
+}
@@ -3,23 +3,45 @@ use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
+use crate::chunker::plan_uploads;
+use crate::commands::lock;
+use crate::config::ProjectConfig;
 use crate::scanner::{diff_files, Scanner};
-use crate::storage::{extract_project_name, History, S3Storage, Snapshot};
-
-pub async fn run(path: &Path, message: Option<&str>, dry_run: bool) -> Result<()> {
+use crate::storage::{BlobEncoding, ChunkRef, Snapshot, Storage, UploadJournal, DEFAULT_BRANCH};
+use crate::workspace::Workspace;
+
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    message: Option<&str>,
+    dry_run: bool,
+    rehash: bool,
+) -> Result<()> {
     let path = fs::canonicalize(path)?;
-    let project_name = extract_project_name(&path);
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let gp_dir = path.join(".gp");
+    let branch = Workspace::load(&gp_dir)?.branch().to_string();
 
     println!("プロジェクト: {}", project_name);
+    if branch != DEFAULT_BRANCH {
+        println!("ブランチ: {}", branch);
+    }
     println!("スキャン中...");
 
-    let scanner = Scanner::new(&path)?;
+    let scanner = Scanner::new(&path)?.with_rehash(rehash);
     let local_files = scanner.scan()?;
 
     println!("ファイル数: {}", local_files.len());
 
-    let storage = S3Storage::new(None).await?;
-    let remote_state = storage.get_remote_state(&project_name).await?;
+    let history = storage.get_history(&project_name).await?;
+    let branch_head = history.as_ref().and_then(|h| h.branch_head(&branch));
+
+    // `current_state.json` は main の状態なので、他のブランチは先頭のスナップショットと比べる
+    let remote_state = match branch_head {
+        Some(head) if branch != DEFAULT_BRANCH => head.files.clone(),
+        _ => storage.get_remote_state(&project_name).await?,
+    };
 
     let changed_files = diff_files(&local_files, &remote_state);
 
@@ -38,18 +60,38 @@ pub async fn run(path: &Path, message: Option<&str>, dry_run: bool) -> Result<()
         return Ok(());
     }
 
-    println!("blobsにアップロード中...");
-    let new_blobs = storage.upload_blobs(&project_name, &changed_files).await?;
-    println!("新規blob: {} 件", new_blobs);
+    if let Some(lock) = lock::held_by_other(storage, &project_name).await? {
+        anyhow::bail!(
+            "{} がプロジェクトをロックしているためプッシュできません（管理者は `gp unlock --force` で解除できます）",
+            lock
+        );
+    }
 
-    storage.save_state(&project_name, &local_files).await?;
+    let known_chunks = branch_head.map(|s| s.chunks.clone()).unwrap_or_default();
+    let mut plan = plan_uploads(&changed_files, &known_chunks)?;
+    // 履歴から参照されているblobはgcで消えないため、送り直しも存在の確認もしない
+    if let Some(history) = &history {
+        let referenced = history.referenced_blobs();
+        plan.blobs
+            .retain(|blob| !referenced.contains_key(&blob.hash));
+    }
 
-    let mut history = storage
-        .get_history(&project_name)
-        .await?
-        .unwrap_or_else(|| History::new(&project_name));
+    let journal = UploadJournal::open(&gp_dir, &storage.location(&project_name))?;
+    if journal.completed_count() > 0 {
+        println!(
+            "前回のプッシュを再開します（確認済みblob: {} 件）",
+            journal.completed_count()
+        );
+    }
 
-    let parent_id = history.head.clone();
+    let staging_dir = gp_dir.join("staging");
+    let encoding = BlobEncoding::new(config.codec()?, storage.keyring(), &staging_dir);
+
+    println!("blobsにアップロード中...");
+    let new_blobs = storage
+        .upload_blobs(&project_name, &plan.blobs, &encoding, &journal)
+        .await?;
+    println!("新規blob: {} 件", new_blobs);
 
     let files_map: HashMap<String, String> = local_files
         .iter()
@@ -61,27 +103,62 @@ pub async fn run(path: &Path, message: Option<&str>, dry_run: bool) -> Result<()
         })
         .collect();
 
-    let total_size: u64 = local_files.iter().map(|f| f.size).sum();
-
-    let snapshot = Snapshot::new(
-        message.map(String::from),
-        files_map,
-        parent_id,
-        total_size,
-        changed_files.len(),
-    );
+    let sizes: HashMap<String, u64> = local_files
+        .iter()
+        .map(|f| (f.relative_path.to_string_lossy().to_string(), f.size))
+        .collect();
 
-    let snapshot_id = snapshot.id.clone();
-    history.add_snapshot(snapshot);
+    // 今回分割したファイルに加え、変更のない大きなファイルのチャンク一覧も引き継ぐ
+    let chunks: HashMap<String, Vec<ChunkRef>> = local_files
+        .iter()
+        .filter_map(|f| {
+            plan.manifests
+                .get(&f.hash)
+                .or_else(|| known_chunks.get(&f.hash))
+                .map(|c| (f.hash.clone(), c.clone()))
+        })
+        .collect();
 
-    storage.save_history(&project_name, &history).await?;
+    // 他の人が同時に同じブランチへプッシュしていたら、その最新スナップショットの上に積み直す
+    let base_head = branch_head.map(|s| s.id.clone());
+    let history = storage
+        .update_history(&project_name, &mut |history| {
+            let head = history.branch_head_id(&branch).map(String::from);
+            if head != base_head {
+                println!("他のプッシュが先に完了したため、その上にスナップショットを追加します");
+            }
+            let snapshot = Snapshot::new(
+                message.map(String::from),
+                files_map.clone(),
+                sizes.clone(),
+                chunks.clone(),
+                head,
+                changed_files.len(),
+            );
+            history.add_snapshot_to(&branch, snapshot);
+            Ok(())
+        })
+        .await?;
+    let snapshot_id = history
+        .branch_head_id(&branch)
+        .unwrap_or_default()
+        .to_string();
+
+    if branch == DEFAULT_BRANCH {
+        storage.sync_state(&project_name, history).await?;
+    }
+    Workspace::record_base(&gp_dir, &snapshot_id)?;
+    journal.clear()?;
+    if staging_dir.exists() {
+        fs::remove_dir_all(&staging_dir)?;
+    }
 
     println!("\nスナップショット: {}", snapshot_id);
     if let Some(msg) = message {
         println!("メッセージ: {}", msg);
     }
 
-    println!("プッシュ完了: s3://{}/{}/", storage.bucket(), project_name);
+    println!("プッシュ完了: {}", storage.location(&project_name));
 
     Ok(())
 }
@@ -0,0 +1,81 @@
+use anyhow::Result;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::storage::remote::Remote;
+use crate::utils::validate_ref_name;
+
+/// 名前付きのリモートを `.gp/config.toml` に追加する
+///
+/// 既定のリモートが（ユーザー設定を含めて）未設定の場合や `make_default` の場合は、既定のリモートにする。
+/// 設定ファイルはリモートの項目だけを書き換え、コメントなどはそのまま残す。
+pub fn run_add(path: &Path, name: &str, url: &str, make_default: bool) -> Result<()> {
+    validate_ref_name(name)?;
+    url.parse::<Remote>()?;
+
+    let user = ProjectConfig::load_user()?;
+    let mut config = load_project(path)?;
+    let made_default = config.add_remote(&user, name, url, make_default)?;
+    config.save_remotes(&user, path)?;
+
+    println!("リモートを追加しました: {} → {}", name, url);
+    if made_default {
+        println!("既定のリモート: {}", name);
+    }
+    Ok(())
+}
+
+/// 名前付きのリモートを設定から削除する。リモートに保存済みのデータは消えない
+pub fn run_remove(path: &Path, name: &str) -> Result<()> {
+    let user = ProjectConfig::load_user()?;
+    let mut config = load_project(path)?;
+    config.remove_remote(&user, name)?;
+    config.save_remotes(&user, path)?;
+
+    println!("リモートを削除しました: {}", name);
+    Ok(())
+}
+
+/// リモートの一覧を表示する。ユーザー設定のリモートも含む
+pub fn run_list(path: &Path) -> Result<()> {
+    let config = ProjectConfig::load(path)?;
+    let default = config.default_remote();
+
+    if config.remotes.is_empty() {
+        match default {
+            Some(remote) => println!("* {}", remote),
+            None => println!("リモートが設定されていません"),
+        }
+        return Ok(());
+    }
+
+    for (name, url) in &config.remotes {
+        let marker = if default.as_deref() == Some(name) {
+            "*"
+        } else {
+            " "
+        };
+        println!("{} {:<20} {}", marker, name, url);
+    }
+    // 既定のリモートがURLで直接指定されている場合
+    if let Some(remote) = default.filter(|r| !config.remotes.contains_key(r)) {
+        println!("* {:<20} {}", "(既定)", remote);
+    }
+    Ok(())
+}
+
+/// 既定のリモートを変更する。ユーザー設定のリモートも指定できる
+pub fn run_default(path: &Path, name: &str) -> Result<()> {
+    let user = ProjectConfig::load_user()?;
+    let mut config = load_project(path)?;
+    config.set_default_remote(&user, name)?;
+    config.save_remotes(&user, path)?;
+
+    println!("既定のリモート: {}", name);
+    Ok(())
+}
+
+/// ユーザー設定を含まない、プロジェクトの `.gp/config.toml` だけを読み込む
+fn load_project(path: &Path) -> Result<ProjectConfig> {
+    Ok(ProjectConfig::load_file(&ProjectConfig::path(path))?)
+}
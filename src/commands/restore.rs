@@ -0,0 +1,133 @@
+use anyhow::Result;
+use chrono::Utc;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::ProjectConfig;
+use crate::scanner::hash_file;
+use crate::storage::{DownloadTarget, Storage};
+use crate::workspace::{self, Workspace};
+
+/// スナップショットから一部のファイルだけを取り出す
+///
+/// `patterns` はパス・フォルダ・globパターン。`output` を指定した場合は、一致した1ファイルを
+/// その名前で書き出す。保存されていない変更を上書きする場合は、`force` でない限り中止する。
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    snapshot_id: &str,
+    patterns: &[String],
+    output: Option<&Path>,
+    force: bool,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    let snapshot = history
+        .find_snapshot(snapshot_id)
+        .ok_or_else(|| anyhow::anyhow!("スナップショットが見つかりません: {}", snapshot_id))?;
+
+    let matched = snapshot.matching_files(patterns)?;
+    if matched.is_empty() {
+        anyhow::bail!(
+            "スナップショット {} に一致するファイルがありません: {}",
+            snapshot.id,
+            patterns.join(" ")
+        );
+    }
+
+    let restores: Vec<(String, PathBuf)> = match output {
+        Some(output) if matched.len() == 1 => {
+            let file = matched.into_iter().next().unwrap_or_default();
+            vec![(file, path.join(output))]
+        }
+        Some(_) => anyhow::bail!(
+            "{} 件のファイルが一致しました。--output は1ファイルだけを復元する場合に指定してください",
+            matched.len()
+        ),
+        None => matched
+            .into_iter()
+            .map(|file| {
+                let dest = path.join(&file);
+                (file, dest)
+            })
+            .collect(),
+    };
+
+    // 元のスナップショットと同じ内容のファイルは保存済みなので上書きしてよい
+    let base = Workspace::load(&path.join(".gp"))?
+        .base_snapshot(&history)
+        .or_else(|| history.head_snapshot());
+
+    let mut targets = Vec::new();
+    let mut restored = Vec::new();
+    let mut unsaved = Vec::new();
+    for (file, dest) in &restores {
+        let hash = &snapshot.files[file];
+        if dest.is_file() {
+            let local_hash = hash_file(dest)?;
+            if &local_hash == hash {
+                println!("  変更なし: {}", file);
+                continue;
+            }
+
+            let relative = dest
+                .strip_prefix(&path)
+                .map_err(|_| anyhow::anyhow!("既に存在します: {}", dest.display()))?
+                .to_string_lossy()
+                .to_string();
+            if base.and_then(|b| b.files.get(&relative)) != Some(&local_hash) {
+                unsaved.push(relative);
+            }
+        }
+
+        targets.extend(DownloadTarget::for_file(snapshot, file, dest.clone()));
+        restored.push((file, dest));
+    }
+
+    if !unsaved.is_empty() {
+        println!("\n保存されていない変更:");
+        for file in &unsaved {
+            println!("  {}", file);
+        }
+        if !force {
+            anyhow::bail!(
+                "保存されていない変更が失われるため中止しました（`gp push` で先に保存するか、--force で .gp/trash に退避してください）"
+            );
+        }
+
+        let label = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        for file in &unsaved {
+            workspace::move_to_trash(&path, file, &label)?;
+        }
+        println!(
+            "{} 件を退避しました: {}\n",
+            unsaved.len(),
+            path.join(".gp/trash").join(&label).display()
+        );
+    }
+
+    if !targets.is_empty() {
+        println!("ダウンロード中...");
+        storage.download_blobs(&project_name, &targets).await?;
+    }
+
+    for (file, dest) in &restored {
+        match dest.strip_prefix(&path) {
+            Ok(relative) if relative != Path::new(file.as_str()) => {
+                println!("  復元: {} → {}", file, relative.display())
+            }
+            Ok(_) => println!("  復元: {}", file),
+            Err(_) => println!("  復元: {} → {}", file, dest.display()),
+        }
+    }
+    println!("\n{} から {} 件を復元しました", snapshot.id, restored.len());
+
+    Ok(())
+}
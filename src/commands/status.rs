@@ -1,18 +1,31 @@
 use anyhow::Result;
+use std::collections::HashMap;
 use std::fs;
 use std::path::Path;
 
-use crate::scanner::{diff_files, Scanner};
-use crate::storage::{extract_project_name, S3Storage};
+use crate::config::ProjectConfig;
+use crate::crypto::Keyring;
+use crate::scanner::{diff_files, ScannedFile, Scanner};
+use crate::storage::{Storage, DEFAULT_BRANCH};
 use crate::utils::format_size;
+use crate::workspace::Workspace;
 
-pub async fn run(path: &Path) -> Result<()> {
+/// 作業コピーとリモートの状態を表示する
+///
+/// 名前付きのリモートは `keyring`（既定のリモートを開くときに読み込んだ鍵）で開き直す。
+pub async fn run(
+    storage: &dyn Storage,
+    keyring: Option<Keyring>,
+    path: &Path,
+    rehash: bool,
+) -> Result<()> {
     let path = fs::canonicalize(path)?;
-    let project_name = extract_project_name(&path);
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
 
     println!("プロジェクト: {}", project_name);
 
-    let scanner = Scanner::new(&path)?;
+    let scanner = Scanner::new(&path)?.with_rehash(rehash);
     let local_files = scanner.scan()?;
 
     println!("ローカルファイル数: {}", local_files.len());
@@ -20,15 +33,102 @@ pub async fn run(path: &Path) -> Result<()> {
     let total_size: u64 = local_files.iter().map(|f| f.size).sum();
     println!("合計サイズ: {}", format_size(total_size));
 
-    let storage = S3Storage::new(None).await?;
-    let remote_state = storage.get_remote_state(&project_name).await?;
+    match storage.get_lock(&project_name).await? {
+        Some(lock) if lock.is_mine() => println!("ロック: 自分がロックしています"),
+        Some(lock) => println!("警告: {} がプロジェクトをロックしています", lock),
+        None => {}
+    }
+
+    let workspace = Workspace::load(&path.join(".gp"))?;
+    let branch = workspace.branch();
+    println!("ブランチ: {}", branch);
+
+    if config.remotes.is_empty() {
+        let remote_state = remote_files(storage, &project_name, branch).await?;
+        if remote_state.is_empty() {
+            println!("リモート: まだプッシュされていません");
+        } else {
+            let changed_files = diff_files(&local_files, &remote_state);
+            println!("変更ファイル数: {}", changed_files.len());
+        }
+        return Ok(());
+    }
 
-    if remote_state.is_empty() {
-        println!("リモート: まだプッシュされていません");
-    } else {
-        let changed_files = diff_files(&local_files, &remote_state);
-        println!("変更ファイル数: {}", changed_files.len());
+    // 名前付きのリモートごとに同期状態を表示する。接続できないリモートがあっても続ける
+    let default = config.default_remote();
+    println!("\nリモート:");
+    for name in config.remotes.keys() {
+        let marker = if default.as_deref() == Some(name) {
+            "*"
+        } else {
+            " "
+        };
+        let remote = match config.remote(Some(name)) {
+            Ok(remote) => remote.connect(keyring.clone(), config.concurrency()).await,
+            Err(e) => Err(e),
+        };
+        let remote_state = match remote {
+            Ok(remote) => remote_files(remote.as_ref(), &project_name, branch).await,
+            Err(e) => Err(e.into()),
+        };
+        print_remote_state(marker, name, remote_state, &local_files);
+    }
+    // 既定のリモートがURLで直接指定されている場合は、開いてあるそのリモートを表示する
+    if let Some(remote) = default.filter(|r| !config.remotes.contains_key(r)) {
+        let remote_state = remote_files(storage, &project_name, branch).await;
+        print_remote_state(
+            "*",
+            &format!("(既定) {}", remote),
+            remote_state,
+            &local_files,
+        );
     }
 
     Ok(())
 }
+
+/// 1つのリモートの同期状態を1行で表示する
+fn print_remote_state(
+    marker: &str,
+    name: &str,
+    remote_state: Result<HashMap<String, String>>,
+    local_files: &[ScannedFile],
+) {
+    match remote_state {
+        Ok(state) if state.is_empty() => {
+            println!("{} {:<20} まだプッシュされていません", marker, name)
+        }
+        Ok(state) => {
+            let changed_files = diff_files(local_files, &state);
+            if changed_files.is_empty() {
+                println!("{} {:<20} 最新", marker, name);
+            } else {
+                println!(
+                    "{} {:<20} 変更ファイル数: {}",
+                    marker,
+                    name,
+                    changed_files.len()
+                );
+            }
+        }
+        Err(e) => println!("{} {:<20} 接続できません: {}", marker, name, e),
+    }
+}
+
+/// リモートにあるブランチの先頭のファイル一覧（パス → ハッシュ）
+///
+/// `current_state.json` は main の状態なので、他のブランチは先頭のスナップショットを使う。
+async fn remote_files(
+    storage: &dyn Storage,
+    project_name: &str,
+    branch: &str,
+) -> Result<HashMap<String, String>> {
+    if branch == DEFAULT_BRANCH {
+        return Ok(storage.get_remote_state(project_name).await?);
+    }
+    Ok(storage
+        .get_history(project_name)
+        .await?
+        .and_then(|h| h.branch_head(branch).map(|s| s.files.clone()))
+        .unwrap_or_default())
+}
@@ -0,0 +1,57 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use crate::commands::checkout;
+use crate::config::ProjectConfig;
+use crate::storage::{Storage, DEFAULT_BRANCH};
+use crate::workspace::Workspace;
+
+/// 作業コピーをブランチの最新スナップショットに置き換え、以降のプッシュ先をそのブランチにする
+///
+/// 保存されていない変更の扱いは `gp checkout` と同じ。
+pub async fn run(storage: &dyn Storage, path: &Path, branch: &str, force: bool) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+    let gp_dir = path.join(".gp");
+
+    if Workspace::load(&gp_dir)?.branch() == branch {
+        println!("既にブランチ '{}' で作業しています", branch);
+        return Ok(());
+    }
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+    if !history.has_branch(branch) {
+        anyhow::bail!(
+            "ブランチが見つかりません: {}（`gp branch {}` で作成できます）",
+            branch,
+            branch
+        );
+    }
+    let head_id = history
+        .branch_head_id(branch)
+        .ok_or_else(|| anyhow::anyhow!("ブランチ '{}' にスナップショットがありません", branch))?
+        .to_string();
+
+    checkout::run(
+        storage,
+        &path,
+        Some(&project_name),
+        &head_id,
+        None,
+        None,
+        force,
+    )
+    .await?;
+
+    let mut workspace = Workspace::load(&gp_dir)?;
+    workspace.branch = (branch != DEFAULT_BRANCH).then(|| branch.to_string());
+    workspace.save(&gp_dir)?;
+
+    println!("ブランチを切り替えました: {}", branch);
+    Ok(())
+}
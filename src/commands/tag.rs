@@ -0,0 +1,120 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::error::GpError;
+use crate::storage::{History, Storage};
+use crate::utils::validate_ref_name;
+use crate::workspace::Workspace;
+
+/// スナップショットにタグを付ける。`name` を省略した場合はタグ一覧を表示する
+///
+/// `snapshot` を省略した場合は作業コピーの元になったスナップショット（不明なら最新）に付ける。
+pub async fn run(
+    storage: &dyn Storage,
+    path: &Path,
+    name: Option<&str>,
+    snapshot: Option<&str>,
+    force: bool,
+) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    let Some(name) = name else {
+        list_tags(&history);
+        return Ok(());
+    };
+    validate_ref_name(name)?;
+    if history.has_branch(name) {
+        anyhow::bail!("'{}' は既にブランチ名として使われています", name);
+    }
+
+    let target = match snapshot {
+        Some(id) => history
+            .find_snapshot(id)
+            .ok_or_else(|| anyhow::anyhow!("スナップショットが見つかりません: {}", id))?,
+        None => Workspace::load(&path.join(".gp"))?
+            .base_snapshot(&history)
+            .or_else(|| history.head_snapshot())
+            .ok_or_else(|| anyhow::anyhow!("スナップショットがありません"))?,
+    };
+    let target_id = target.id.clone();
+
+    match history.tags.get(name) {
+        Some(id) if id == &target_id => {
+            println!("タグ '{}' は既に {} に付いています", name, target_id);
+            return Ok(());
+        }
+        Some(id) if !force => anyhow::bail!(
+            "タグ '{}' は既に {} に付いています（--force で付け替えます）",
+            name,
+            id
+        ),
+        _ => {}
+    }
+
+    storage
+        .update_history(&project_name, &mut |history: &mut History| {
+            if !history.snapshots.iter().any(|s| s.id == target_id) {
+                return Err(GpError::StorageError(format!(
+                    "スナップショットが見つかりません: {}",
+                    target_id
+                )));
+            }
+            history.tags.insert(name.to_string(), target_id.clone());
+            Ok(())
+        })
+        .await?;
+
+    println!("タグを付けました: {} → {}", name, target_id);
+    Ok(())
+}
+
+/// タグを削除する（スナップショットは残る）
+pub async fn run_delete(storage: &dyn Storage, path: &Path, name: &str) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+    if !history.tags.contains_key(name) {
+        anyhow::bail!("タグが見つかりません: {}", name);
+    }
+
+    storage
+        .update_history(&project_name, &mut |history: &mut History| {
+            history.tags.remove(name);
+            Ok(())
+        })
+        .await?;
+
+    println!("タグを削除しました: {}", name);
+    Ok(())
+}
+
+fn list_tags(history: &History) {
+    if history.tags.is_empty() {
+        println!("タグはありません");
+        return;
+    }
+
+    for (name, id) in &history.tags {
+        let message = history
+            .snapshots
+            .iter()
+            .find(|s| &s.id == id)
+            .and_then(|s| s.message.as_deref())
+            .unwrap_or("");
+        println!("{:<20} {} {}", name, id, message);
+    }
+}
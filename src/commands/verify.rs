@@ -0,0 +1,67 @@
+use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+use crate::config::ProjectConfig;
+use crate::error::GpError;
+use crate::storage::{DownloadTarget, Storage};
+use crate::utils::format_size;
+
+pub async fn run(storage: &dyn Storage, path: &Path, snapshot_id: Option<&str>) -> Result<()> {
+    let path = fs::canonicalize(path)?;
+    let config = ProjectConfig::load(&path)?;
+    let project_name = config.project_name(&path);
+
+    let history = storage
+        .get_history(&project_name)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("プロジェクト '{}' の履歴が見つかりません", project_name))?;
+
+    let snapshot = match snapshot_id {
+        Some(id) => history
+            .find_snapshot(id)
+            .ok_or_else(|| anyhow::anyhow!("スナップショットが見つかりません: {}", id))?,
+        None => history
+            .head_snapshot()
+            .ok_or_else(|| anyhow::anyhow!("スナップショットがありません"))?,
+    };
+
+    // 同じ内容のファイルやチャンクは1回だけ検証する
+    let mut blobs: BTreeMap<String, u64> = BTreeMap::new();
+    let mut owners: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for target in DownloadTarget::for_snapshot(snapshot, Path::new("")) {
+        blobs.insert(target.hash.clone(), target.size);
+        owners
+            .entry(target.hash)
+            .or_default()
+            .insert(target.path.display().to_string());
+    }
+
+    println!("検証中: {}", snapshot.id);
+    println!(
+        "blob数: {} ({})\n",
+        blobs.len(),
+        format_size(blobs.values().sum())
+    );
+
+    let failures = storage.verify_blobs(&project_name, &blobs).await;
+
+    if failures.is_empty() {
+        println!("検証完了: すべてのblobが正常です");
+        return Ok(());
+    }
+
+    for (hash, error) in &failures {
+        match error {
+            GpError::HashMismatch { .. } | GpError::CorruptBlob(_) => println!("破損: {}", hash),
+            GpError::BlobNotFound(_) => println!("欠落: {}", hash),
+            other => println!("エラー: {} ({})", hash, other),
+        }
+        for file in owners.get(hash).into_iter().flatten() {
+            println!("  {}", file);
+        }
+    }
+
+    anyhow::bail!("{} 件のblobに問題があります", failures.len())
+}
@@ -0,0 +1,710 @@
+use crate::error::{GpError, Result};
+use crate::storage::codec::Codec;
+use crate::storage::remote::Remote;
+use crate::storage::{Snapshot, DEFAULT_CONCURRENCY};
+use chrono::{DateTime, Datelike, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+const CONFIG_FILE: &str = "config.toml";
+const REMOTE_ENV: &str = "GROOVEPUSH_REMOTE";
+/// 設定ファイル導入前の接続先（S3バケット名）。`remote` が無い場合のみ使う
+const LEGACY_BUCKET_ENV: &str = "GROOVEPUSH_BUCKET";
+const COMPRESSION_ENV: &str = "GROOVEPUSH_COMPRESSION";
+
+/// `gp init` で書き出す保持ルールの例（未設定の場合）
+const RETENTION_TEMPLATE: &str = "
+# スナップショットの保持ルール（gp prune）
+# [retention]
+# keep_all_days = 7
+# daily_days = 30
+# weekly_days = 365
+";
+
+/// プロジェクト設定（`.gp/config.toml`）
+///
+/// 同じ形式のユーザー設定（`~/.config/groovepush/config.toml`）があれば、
+/// プロジェクトで指定していない項目のデフォルトとして使う（`name` を除く）。
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectConfig {
+    /// リモートでのプロジェクト名。フォルダ名を変えても同じプロジェクトとして扱う
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// 既定のリモート。`remotes` の名前、または接続先のURL（`s3://bucket` / `file:///path`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote: Option<String>,
+    /// 同時に転送するblob数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub concurrency: Option<usize>,
+    /// blobの圧縮方式（`zstd` / `none`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// 名前付きのリモート（名前 → 接続先のURL）
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub remotes: BTreeMap<String, String>,
+    /// スナップショットの保持ルール（`gp prune`）。未設定なら何も削除しない
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retention: Option<RetentionPolicy>,
+}
+
+impl ProjectConfig {
+    pub fn path(project_root: &Path) -> PathBuf {
+        project_root.join(".gp").join(CONFIG_FILE)
+    }
+
+    /// `~/.config/groovepush/config.toml`
+    pub fn user_path() -> Option<PathBuf> {
+        std::env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join(".config/groovepush")
+                .join(CONFIG_FILE)
+        })
+    }
+
+    /// プロジェクト設定を読み込み、指定されていない項目をユーザー設定で補う
+    pub fn load(project_root: &Path) -> Result<Self> {
+        let project = Self::load_file(&Self::path(project_root))?;
+        Ok(project.with_defaults(Self::load_user()?))
+    }
+
+    /// ユーザー設定だけを読み込む。ファイルが無ければデフォルト
+    pub fn load_user() -> Result<Self> {
+        match Self::user_path() {
+            Some(path) => Self::load_file(&path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// 1つの設定ファイルを読み込む。ファイルが無ければデフォルト
+    pub fn load_file(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&text)
+            .map_err(|e| GpError::ConfigError(format!("{}: {}", path.display(), e)))
+    }
+
+    /// 指定されていない項目を `defaults` で補う。プロジェクト名は引き継がない
+    ///
+    /// 名前付きのリモートは両方を合わせ、同じ名前はプロジェクトの設定を優先する。
+    pub fn with_defaults(self, defaults: Self) -> Self {
+        let mut remotes = defaults.remotes;
+        remotes.extend(self.remotes);
+        Self {
+            name: self.name,
+            remote: self.remote.or(defaults.remote),
+            concurrency: self.concurrency.or(defaults.concurrency),
+            codec: self.codec.or(defaults.codec),
+            remotes,
+            retention: self.retention.or(defaults.retention),
+        }
+    }
+
+    /// 新しいプロジェクト（`gp init`・`gp clone`）に書き出す設定
+    ///
+    /// 現在有効な接続先・同時転送数・圧縮方式を明示的に書き込み、後からユーザー設定や
+    /// 環境変数が変わってもプロジェクトの接続先が変わらないようにする。
+    pub fn for_new_project(&self, name: &str) -> Result<Self> {
+        Ok(Self {
+            name: Some(name.to_string()),
+            remote: self.default_remote(),
+            concurrency: Some(self.concurrency()),
+            codec: Some(self.codec()?.to_string()),
+            remotes: self.remotes.clone(),
+            retention: self.retention.clone(),
+        })
+    }
+
+    /// `.gp/config.toml` に書き出す
+    ///
+    /// ファイル全体を書き直すため、手で書いたコメントは残らない。既存の設定のリモートだけを
+    /// 変える場合は `save_remotes` を使う。
+    pub fn save(&self, project_root: &Path) -> Result<()> {
+        let mut text = String::from("# GroovePush プロジェクト設定\n");
+        text.push_str(
+            &toml::to_string_pretty(self).map_err(|e| GpError::ConfigError(e.to_string()))?,
+        );
+        if self.retention.is_none() {
+            text.push_str(RETENTION_TEMPLATE);
+        }
+
+        let path = Self::path(project_root);
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, text)?;
+        Ok(())
+    }
+
+    /// 既定のリモートと名前付きのリモートだけを `.gp/config.toml` に書き戻す
+    ///
+    /// 既存のファイルの `remote` と `[remotes]` だけを置き換え、コメントや他の項目はそのまま残す。
+    /// ファイルが無ければ（設定ファイル導入前のプロジェクトなど）、`user` で補った設定を
+    /// `migrate_legacy` と同じくフォルダ名をプロジェクト名として書き出す。
+    pub fn save_remotes(&self, user: &Self, project_root: &Path) -> Result<()> {
+        let path = Self::path(project_root);
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let config = self.clone().with_defaults(user.clone());
+                return config.pin_legacy(project_root).map(|_| ());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let config_error =
+            |e: &dyn std::fmt::Display| GpError::ConfigError(format!("{}: {}", path.display(), e));
+        let mut doc: toml_edit::DocumentMut = text.parse().map_err(|e| config_error(&e))?;
+
+        match &self.remote {
+            Some(remote) => doc["remote"] = toml_edit::value(remote.as_str()),
+            None => {
+                doc.remove("remote");
+            }
+        }
+
+        if self.remotes.is_empty() {
+            doc.remove("remotes");
+        } else {
+            let remotes = doc
+                .entry("remotes")
+                .or_insert(toml_edit::table())
+                .as_table_like_mut()
+                .ok_or_else(|| config_error(&"remotes はテーブルである必要があります"))?;
+            let removed: Vec<String> = remotes
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .filter(|name| !self.remotes.contains_key(name))
+                .collect();
+            for name in removed {
+                remotes.remove(&name);
+            }
+            for (name, url) in &self.remotes {
+                if remotes.get(name).and_then(|v| v.as_str()) != Some(url.as_str()) {
+                    remotes.insert(name, toml_edit::value(url.as_str()));
+                }
+            }
+        }
+
+        std::fs::write(path, doc.to_string())?;
+        Ok(())
+    }
+
+    /// 名前付きのリモートを追加する。`user` はユーザー設定
+    ///
+    /// 既定のリモートが（ユーザー設定を含めて）未設定の場合や `make_default` の場合は、
+    /// 既定のリモートにする。既定のリモートにした場合は `true` を返す。
+    pub fn add_remote(
+        &mut self,
+        user: &Self,
+        name: &str,
+        url: &str,
+        make_default: bool,
+    ) -> Result<bool> {
+        if self.remotes.contains_key(name) {
+            return Err(GpError::ConfigError(format!(
+                "リモート '{}' は既にあります",
+                name
+            )));
+        }
+        self.remotes.insert(name.to_string(), url.to_string());
+        let make_default = make_default || (self.remote.is_none() && user.remote.is_none());
+        if make_default {
+            self.remote = Some(name.to_string());
+        }
+        Ok(make_default)
+    }
+
+    /// 名前付きのリモートを削除する。既定のリモート（ユーザー設定を含む）は削除できない
+    pub fn remove_remote(&mut self, user: &Self, name: &str) -> Result<()> {
+        if !self.remotes.contains_key(name) {
+            return Err(GpError::ConfigError(format!(
+                "リモートが見つかりません: {}",
+                name
+            )));
+        }
+        if self.remote.as_deref().or(user.remote.as_deref()) == Some(name) {
+            return Err(GpError::ConfigError(
+                "既定のリモートは削除できません（先に `gp remote default` で変更してください）"
+                    .to_string(),
+            ));
+        }
+        self.remotes.remove(name);
+        Ok(())
+    }
+
+    /// 既定のリモートを変更する。ユーザー設定の名前付きのリモートも指定できる
+    pub fn set_default_remote(&mut self, user: &Self, name: &str) -> Result<()> {
+        if !self.remotes.contains_key(name) && !user.remotes.contains_key(name) {
+            return Err(GpError::ConfigError(format!(
+                "リモートが見つかりません: {}（`gp remote add` で追加できます）",
+                name
+            )));
+        }
+        self.remote = Some(name.to_string());
+        Ok(())
+    }
+
+    /// 設定ファイル導入前に作られたプロジェクト（`.gp/` はあるが `config.toml` が無い）の設定を書き出す
+    ///
+    /// フォルダ名をプロジェクト名として、現在有効な接続先とともに記録し、以降はフォルダ名を
+    /// 変えても同じリモートのプロジェクトを使う。書き出した場合はその設定を返す。
+    /// 接続先が分からない場合は推測せず、`gp init` で設定するよう求める。
+    pub fn migrate_legacy(project_root: &Path) -> Result<Option<Self>> {
+        let path = Self::path(project_root);
+        let is_legacy = path.parent().is_some_and(|gp_dir| gp_dir.is_dir()) && !path.exists();
+        if !is_legacy {
+            return Ok(None);
+        }
+
+        Self::load(project_root)?.pin_legacy(project_root).map(Some)
+    }
+
+    fn pin_legacy(&self, project_root: &Path) -> Result<Self> {
+        if self.default_remote().is_none() {
+            return Err(GpError::ConfigError(format!(
+                "{} がありません。`gp init --name <リモートでのプロジェクト名> --remote <URL>` で設定してください（以前の既定の接続先は s3://groovepush-bucket です）",
+                Self::path(project_root).display()
+            )));
+        }
+
+        let config = self.for_new_project(&folder_name(project_root))?;
+        config.save(project_root)?;
+        Ok(config)
+    }
+
+    /// リモートでのプロジェクト名。未設定ならフォルダ名
+    pub fn project_name(&self, project_root: &Path) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => folder_name(project_root),
+        }
+    }
+
+    /// 既定のリモート（名前またはURL）。`GROOVEPUSH_REMOTE` 環境変数があれば優先する
+    ///
+    /// どちらも無ければ、従来の `GROOVEPUSH_BUCKET` 環境変数を `s3://` のリモートとして使う。
+    pub fn default_remote(&self) -> Option<String> {
+        std::env::var(REMOTE_ENV)
+            .ok()
+            .or_else(|| self.remote.clone())
+            .or_else(|| {
+                std::env::var(LEGACY_BUCKET_ENV)
+                    .ok()
+                    .map(|bucket| format!("s3://{}", bucket))
+            })
+    }
+
+    /// リモートの名前を接続先のURLに置き換える。名前でなければそのまま返す
+    pub fn remote_url<'a>(&'a self, remote: &'a str) -> &'a str {
+        self.remotes.get(remote).map_or(remote, String::as_str)
+    }
+
+    /// `remote`（名前またはURL）の接続先。省略した場合は既定のリモート
+    pub fn remote(&self, remote: Option<&str>) -> Result<Remote> {
+        let remote = match remote {
+            Some(remote) => remote.to_string(),
+            None => self.default_remote().ok_or_else(|| {
+                GpError::ConfigError(
+                    "リモートが設定されていません（.gp/config.toml または ~/.config/groovepush/config.toml の remote を指定してください）"
+                        .to_string(),
+                )
+            })?,
+        };
+        if !remote.contains("://") && !self.remotes.contains_key(&remote) {
+            return Err(GpError::ConfigError(format!(
+                "リモートが見つかりません: {}（`gp remote list` で確認できます）",
+                remote
+            )));
+        }
+        self.remote_url(&remote).parse()
+    }
+
+    pub fn concurrency(&self) -> usize {
+        self.concurrency.unwrap_or(DEFAULT_CONCURRENCY).max(1)
+    }
+
+    /// 圧縮方式。`GROOVEPUSH_COMPRESSION` 環境変数があれば優先し、未設定時はzstd
+    pub fn codec(&self) -> Result<Codec> {
+        match std::env::var(COMPRESSION_ENV)
+            .ok()
+            .or_else(|| self.codec.clone())
+        {
+            Some(value) => value.parse(),
+            None => Ok(Codec::Zstd),
+        }
+    }
+}
+
+/// 設定が無い場合のプロジェクト名（フォルダ名）
+fn folder_name(path: &Path) -> String {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("unnamed_project")
+        .to_string()
+}
+
+/// スナップショットの保持ルール
+///
+/// 例: 7日以内はすべて、30日以内は1日1件、1年以内は1週間に1件を残す。
+/// いずれのルールにも当てはまらないスナップショットが `gp prune` の削除対象になる。
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// この日数以内のスナップショットはすべて残す
+    #[serde(default)]
+    pub keep_all_days: u32,
+    /// この日数以内は1日ごとに最新の1件を残す
+    #[serde(default)]
+    pub daily_days: u32,
+    /// この日数以内は1週間ごとに最新の1件を残す
+    #[serde(default)]
+    pub weekly_days: u32,
+}
+
+impl RetentionPolicy {
+    /// 残すスナップショットのIDを返す。日・週の区切りはUTC
+    pub fn retained(&self, snapshots: &[Snapshot], now: DateTime<Utc>) -> HashSet<String> {
+        let mut newest_first: Vec<&Snapshot> = snapshots.iter().collect();
+        newest_first.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+
+        let within = |snapshot: &Snapshot, days: u32| {
+            days > 0 && snapshot.created_at > now - Duration::days(days as i64)
+        };
+
+        let mut keep = HashSet::new();
+        let mut days = HashSet::new();
+        let mut weeks = HashSet::new();
+
+        for snapshot in newest_first {
+            let date = snapshot.created_at.date_naive();
+            let week = date.iso_week();
+
+            // 新しい順に見るため、各期間で最初に現れたものがその期間の最新
+            let daily = within(snapshot, self.daily_days) && days.insert(date);
+            let weekly =
+                within(snapshot, self.weekly_days) && weeks.insert((week.year(), week.week()));
+
+            if within(snapshot, self.keep_all_days) || daily || weekly {
+                keep.insert(snapshot.id.clone());
+            }
+        }
+
+        keep
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::history::SnapshotMeta;
+    use chrono::TimeZone;
+    use std::collections::HashMap;
+
+    fn snapshot_at(created_at: DateTime<Utc>) -> Snapshot {
+        Snapshot {
+            id: created_at.format("%Y%m%dT%H%M%S%.3fZ").to_string(),
+            created_at,
+            message: None,
+            files: HashMap::new(),
+            sizes: HashMap::new(),
+            chunks: HashMap::new(),
+            parent_id: None,
+            meta: SnapshotMeta {
+                file_count: 0,
+                total_size: 0,
+                changed_count: 0,
+            },
+        }
+    }
+
+    #[test]
+    fn test_load_missing_config_is_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = ProjectConfig::path(dir.path());
+        assert_eq!(
+            ProjectConfig::load_file(&path).unwrap(),
+            ProjectConfig::default()
+        );
+
+        std::fs::create_dir_all(dir.path().join(".gp")).unwrap();
+        std::fs::write(
+            &path,
+            "name = \"song\"\n[retention]\nkeep_all_days = 7\nweekly_days = 365\n",
+        )
+        .unwrap();
+        let config = ProjectConfig::load_file(&path).unwrap();
+        assert_eq!(config.project_name(Path::new("/music/renamed")), "song");
+        let policy = config.retention.unwrap();
+        assert_eq!(policy.keep_all_days, 7);
+        assert_eq!(policy.daily_days, 0);
+        assert_eq!(policy.weekly_days, 365);
+    }
+
+    #[test]
+    fn test_user_config_fills_missing_values() {
+        let project = ProjectConfig {
+            name: Some("song".to_string()),
+            concurrency: Some(4),
+            ..Default::default()
+        };
+        let user = ProjectConfig {
+            name: Some("ignored".to_string()),
+            remote: Some("file:///mnt/nas/groovepush".to_string()),
+            concurrency: Some(16),
+            codec: Some("none".to_string()),
+            remotes: BTreeMap::new(),
+            retention: None,
+        };
+
+        let config = project.with_defaults(user);
+        assert_eq!(config.name.as_deref(), Some("song"));
+        assert_eq!(config.remote.as_deref(), Some("file:///mnt/nas/groovepush"));
+        assert_eq!(config.concurrency(), 4);
+        assert_eq!(config.codec.as_deref(), Some("none"));
+        assert_eq!(
+            ProjectConfig::default().project_name(Path::new("/music/song")),
+            "song"
+        );
+    }
+
+    #[test]
+    fn test_saved_config_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = ProjectConfig {
+            name: Some("song".to_string()),
+            remote: Some("s3://my-bucket".to_string()),
+            concurrency: Some(8),
+            codec: Some("zstd".to_string()),
+            remotes: BTreeMap::from([("nas".to_string(), "file:///mnt/nas".to_string())]),
+            retention: None,
+        };
+        config.save(dir.path()).unwrap();
+
+        let text = std::fs::read_to_string(ProjectConfig::path(dir.path())).unwrap();
+        assert!(text.contains("# [retention]"));
+        assert_eq!(
+            ProjectConfig::load_file(&ProjectConfig::path(dir.path())).unwrap(),
+            config
+        );
+    }
+
+    #[test]
+    fn test_legacy_project_pins_folder_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("My Song");
+        std::fs::create_dir_all(root.join(".gp")).unwrap();
+
+        let user = ProjectConfig {
+            remote: Some("file:///mnt/nas".to_string()),
+            ..Default::default()
+        };
+        let pinned = user.pin_legacy(&root).unwrap();
+        assert_eq!(pinned.name.as_deref(), Some("My Song"));
+
+        let saved = ProjectConfig::load_file(&ProjectConfig::path(&root)).unwrap();
+        assert_eq!(saved, pinned);
+        assert_eq!(saved.project_name(&dir.path().join("renamed")), "My Song");
+        assert!(ProjectConfig::migrate_legacy(&root).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_named_remotes_resolve_to_urls() {
+        let project = ProjectConfig {
+            remote: Some("studio".to_string()),
+            remotes: BTreeMap::from([("studio".to_string(), "file:///mnt/nas".to_string())]),
+            ..Default::default()
+        };
+        let user = ProjectConfig {
+            remotes: BTreeMap::from([
+                ("studio".to_string(), "file:///mnt/old".to_string()),
+                ("offsite".to_string(), "s3://my-bucket".to_string()),
+            ]),
+            ..Default::default()
+        };
+        let config = project.with_defaults(user);
+
+        assert_eq!(
+            config.remote(Some("studio")).unwrap(),
+            Remote::Local {
+                root: PathBuf::from("/mnt/nas")
+            }
+        );
+        assert_eq!(
+            config.remote(Some("offsite")).unwrap(),
+            Remote::S3 {
+                bucket: "my-bucket".to_string()
+            }
+        );
+        assert_eq!(
+            config.remote(Some("file:///tmp/backup")).unwrap(),
+            Remote::Local {
+                root: PathBuf::from("/tmp/backup")
+            }
+        );
+        assert!(config.remote(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_first_remote_becomes_default() {
+        let no_default = ProjectConfig::default();
+        let mut config = ProjectConfig::default();
+        assert!(config
+            .add_remote(&no_default, "studio", "file:///mnt/nas", false)
+            .unwrap());
+        assert!(!config
+            .add_remote(&no_default, "offsite", "s3://my-bucket", false)
+            .unwrap());
+        assert_eq!(config.remote.as_deref(), Some("studio"));
+        assert!(config
+            .add_remote(&no_default, "studio", "file:///mnt/other", false)
+            .is_err());
+
+        // ユーザー設定に既定のリモートがあれば、明示しない限り変えない
+        let user = ProjectConfig {
+            remote: Some("s3://user-bucket".to_string()),
+            ..Default::default()
+        };
+        let mut config = ProjectConfig::default();
+        assert!(!config
+            .add_remote(&user, "studio", "file:///mnt/nas", false)
+            .unwrap());
+        assert_eq!(config.remote, None);
+        assert!(config
+            .add_remote(&user, "offsite", "s3://my-bucket", true)
+            .unwrap());
+        assert_eq!(config.remote.as_deref(), Some("offsite"));
+    }
+
+    #[test]
+    fn test_default_remote_cannot_be_removed() {
+        let user = ProjectConfig::default();
+        let mut config = ProjectConfig::default();
+        config
+            .add_remote(&user, "studio", "file:///mnt/nas", false)
+            .unwrap();
+        config
+            .add_remote(&user, "offsite", "s3://my-bucket", false)
+            .unwrap();
+
+        assert!(config.remove_remote(&user, "studio").is_err());
+        assert!(config.remove_remote(&user, "missing").is_err());
+        config.remove_remote(&user, "offsite").unwrap();
+        assert!(!config.remotes.contains_key("offsite"));
+
+        // ユーザー設定の既定のリモートも削除できない
+        let user = ProjectConfig {
+            remote: Some("offsite".to_string()),
+            ..Default::default()
+        };
+        let mut config = ProjectConfig {
+            remotes: BTreeMap::from([("offsite".to_string(), "s3://my-bucket".to_string())]),
+            ..Default::default()
+        };
+        assert!(config.remove_remote(&user, "offsite").is_err());
+    }
+
+    #[test]
+    fn test_default_can_be_a_user_remote() {
+        let user = ProjectConfig {
+            remotes: BTreeMap::from([("offsite".to_string(), "s3://my-bucket".to_string())]),
+            ..Default::default()
+        };
+        let mut config = ProjectConfig::default();
+        config.set_default_remote(&user, "offsite").unwrap();
+        assert!(config.set_default_remote(&user, "missing").is_err());
+        assert_eq!(
+            config.with_defaults(user).remote(None).unwrap(),
+            Remote::S3 {
+                bucket: "my-bucket".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_save_remotes_keeps_comments() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = ProjectConfig::path(dir.path());
+        std::fs::create_dir_all(dir.path().join(".gp")).unwrap();
+        std::fs::write(
+            &path,
+            "# スタジオ用\nname = \"song\"\nremote = \"studio\"\n\n[remotes]\n# NAS\nstudio = \"file:///mnt/nas\"\nold = \"file:///mnt/old\"\n",
+        )
+        .unwrap();
+
+        let mut config = ProjectConfig::load_file(&path).unwrap();
+        let user = ProjectConfig::default();
+        config.remove_remote(&user, "old").unwrap();
+        config
+            .add_remote(&user, "offsite", "s3://my-bucket", true)
+            .unwrap();
+        config.save_remotes(&user, dir.path()).unwrap();
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# スタジオ用"));
+        assert!(text.contains("# NAS"));
+        assert!(!text.contains("old"));
+        assert_eq!(ProjectConfig::load_file(&path).unwrap(), config);
+    }
+
+    #[test]
+    fn test_remote_add_pins_legacy_project_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("My Song");
+        std::fs::create_dir_all(root.join(".gp")).unwrap();
+
+        let user = ProjectConfig::default();
+        let mut config = ProjectConfig::load_file(&ProjectConfig::path(&root)).unwrap();
+        config
+            .add_remote(&user, "studio", "file:///mnt/nas", false)
+            .unwrap();
+        config.save_remotes(&user, &root).unwrap();
+
+        let saved = ProjectConfig::load_file(&ProjectConfig::path(&root)).unwrap();
+        assert_eq!(saved.project_name(&dir.path().join("renamed")), "My Song");
+        assert_eq!(saved.remote.as_deref(), Some("studio"));
+        assert_eq!(saved.remotes, config.remotes);
+        assert!(ProjectConfig::migrate_legacy(&root).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retention_thins_out_old_snapshots() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 31, 12, 0, 0).unwrap();
+        let policy = RetentionPolicy {
+            keep_all_days: 7,
+            daily_days: 30,
+            weekly_days: 365,
+        };
+
+        let recent_a = snapshot_at(now - Duration::hours(30));
+        let recent_b = snapshot_at(now - Duration::hours(31));
+        // 10日前の同じ日に2件 → 新しい方だけ
+        let day_new = snapshot_at(Utc.with_ymd_and_hms(2026, 3, 21, 18, 0, 0).unwrap());
+        let day_old = snapshot_at(Utc.with_ymd_and_hms(2026, 3, 21, 9, 0, 0).unwrap());
+        // 60日前の同じ週（月曜と水曜）に2件 → 新しい方だけ
+        let week_new = snapshot_at(Utc.with_ymd_and_hms(2026, 1, 28, 12, 0, 0).unwrap());
+        let week_old = snapshot_at(Utc.with_ymd_and_hms(2026, 1, 26, 12, 0, 0).unwrap());
+        // 1年以上前 → 削除
+        let ancient = snapshot_at(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap());
+
+        let snapshots = vec![
+            ancient.clone(),
+            week_old.clone(),
+            week_new.clone(),
+            day_old.clone(),
+            day_new.clone(),
+            recent_b.clone(),
+            recent_a.clone(),
+        ];
+        let keep = policy.retained(&snapshots, now);
+
+        assert!(keep.contains(&recent_a.id));
+        assert!(keep.contains(&recent_b.id));
+        assert!(keep.contains(&day_new.id));
+        assert!(!keep.contains(&day_old.id));
+        assert!(keep.contains(&week_new.id));
+        assert!(!keep.contains(&week_old.id));
+        assert!(!keep.contains(&ancient.id));
+    }
+}
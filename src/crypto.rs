@@ -0,0 +1,595 @@
+use crate::error::{GpError, Result};
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Nonce};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const KEY_FILE_ENV: &str = "GROOVEPUSH_KEY_FILE";
+const PASSPHRASE_ENV: &str = "GROOVEPUSH_PASSPHRASE";
+const KEY_FILE_VERSION: u32 = 1;
+
+pub const KEY_LEN: usize = 32;
+pub const KEY_ID_LEN: usize = 8;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// 暗号化の単位。blobはこのサイズごとに独立したAES-GCMセグメントになる
+const SEGMENT_SIZE: usize = 1024 * 1024;
+const SEALED_SEGMENT_SIZE: usize = NONCE_LEN + SEGMENT_SIZE + TAG_LEN;
+
+/// パスフレーズからの鍵導出パラメータ（Argon2id）
+const KDF_MEMORY_KIB: u32 = 64 * 1024;
+const KDF_ITERATIONS: u32 = 3;
+const KDF_PARALLELISM: u32 = 1;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// blob・メタデータの暗号化に使うデータ鍵
+#[derive(Clone)]
+pub struct DataKey {
+    id: [u8; KEY_ID_LEN],
+    key: [u8; KEY_LEN],
+    created_at: DateTime<Utc>,
+}
+
+impl DataKey {
+    fn generate() -> Self {
+        let mut key = [0u8; KEY_LEN];
+        OsRng.fill_bytes(&mut key);
+        Self::from_bytes(key, Utc::now())
+    }
+
+    fn from_bytes(key: [u8; KEY_LEN], created_at: DateTime<Utc>) -> Self {
+        let digest = Sha256::new()
+            .chain_update(b"groovepush key id")
+            .chain_update(key)
+            .finalize();
+        let mut id = [0u8; KEY_ID_LEN];
+        id.copy_from_slice(&digest[..KEY_ID_LEN]);
+        Self {
+            id,
+            key,
+            created_at,
+        }
+    }
+
+    pub fn id(&self) -> &[u8; KEY_ID_LEN] {
+        &self.id
+    }
+
+    /// 表示用の鍵ID
+    pub fn id_hex(&self) -> String {
+        hex::encode(self.id)
+    }
+
+    fn subkey(&self, label: &[u8]) -> [u8; KEY_LEN] {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(&self.key).expect("HMACは任意長の鍵を受け付ける");
+        mac.update(label);
+        mac.finalize().into_bytes().into()
+    }
+
+    /// 平文と関連データから決定的にnonceを作り、AES-256-GCMで暗号化する
+    ///
+    /// 同じ鍵で同じ内容を暗号化すると同じ暗号文になるため、鍵を共有していれば
+    /// 暗号化したままでも重複排除できる。内容が異なればnonceも（HMACの衝突が無い限り）異なる。
+    fn seal(&self, plaintext: &[u8], aad: &[u8]) -> Vec<u8> {
+        let mut mac = <HmacSha256 as Mac>::new_from_slice(&self.subkey(b"groovepush nonce"))
+            .expect("HMACは任意長の鍵を受け付ける");
+        mac.update(aad);
+        mac.update(plaintext);
+        let tag = mac.finalize().into_bytes();
+        let nonce = Nonce::from_slice(&tag[..NONCE_LEN]);
+
+        let cipher = Aes256Gcm::new(&self.subkey(b"groovepush encrypt").into());
+        let ciphertext = cipher
+            .encrypt(
+                nonce,
+                Payload {
+                    msg: plaintext,
+                    aad,
+                },
+            )
+            .expect("AES-GCMの暗号化は失敗しない");
+
+        let mut sealed = Vec::with_capacity(NONCE_LEN + ciphertext.len());
+        sealed.extend_from_slice(nonce);
+        sealed.extend_from_slice(&ciphertext);
+        sealed
+    }
+
+    fn open(&self, sealed: &[u8], aad: &[u8]) -> io::Result<Vec<u8>> {
+        if sealed.len() < NONCE_LEN + TAG_LEN {
+            return Err(decrypt_error());
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let cipher = Aes256Gcm::new(&self.subkey(b"groovepush encrypt").into());
+        cipher
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad,
+                },
+            )
+            .map_err(|_| decrypt_error())
+    }
+}
+
+fn decrypt_error() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "復号に失敗しました（鍵が違うか、データが壊れています）",
+    )
+}
+
+fn segment_aad(header: &[u8], index: u64, last: bool) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(header.len() + 9);
+    aad.extend_from_slice(header);
+    aad.extend_from_slice(&index.to_be_bytes());
+    aad.push(last as u8);
+    aad
+}
+
+/// 書き込まれた平文をセグメントごとに暗号化して `inner` に書き出す
+///
+/// `header` は各セグメントの関連データに含め、ヘッダの差し替えを検出する。
+/// 最後のセグメントには終端フラグを付け、途中で切り詰められたblobを検出する。
+pub struct SealWriter<W: Write> {
+    inner: W,
+    key: DataKey,
+    header: Vec<u8>,
+    buf: Vec<u8>,
+    index: u64,
+}
+
+impl<W: Write> SealWriter<W> {
+    pub fn new(inner: W, key: &DataKey, header: &[u8]) -> Self {
+        Self {
+            inner,
+            key: key.clone(),
+            header: header.to_vec(),
+            buf: Vec::with_capacity(SEGMENT_SIZE),
+            index: 0,
+        }
+    }
+
+    fn seal_segment(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let aad = segment_aad(&self.header, self.index, last);
+        let sealed = self.key.seal(&self.buf[..len], &aad);
+        self.inner.write_all(&sealed)?;
+        self.buf.drain(..len);
+        self.index += 1;
+        Ok(())
+    }
+
+    /// 残りを最後のセグメントとして書き出し、`inner` を返す
+    pub fn finish(mut self) -> io::Result<W> {
+        self.seal_segment(self.buf.len(), true)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for SealWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        // 後続のデータがあるときだけ途中のセグメントとして確定する
+        while self.buf.len() > SEGMENT_SIZE {
+            self.seal_segment(SEGMENT_SIZE, false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// `SealWriter` で暗号化されたデータを復号して `inner` に書き出す
+pub struct OpenWriter<W: Write> {
+    inner: W,
+    key: DataKey,
+    header: Vec<u8>,
+    buf: Vec<u8>,
+    index: u64,
+}
+
+impl<W: Write> OpenWriter<W> {
+    pub fn new(inner: W, key: &DataKey, header: &[u8]) -> Self {
+        Self {
+            inner,
+            key: key.clone(),
+            header: header.to_vec(),
+            buf: Vec::with_capacity(SEALED_SEGMENT_SIZE),
+            index: 0,
+        }
+    }
+
+    fn open_segment(&mut self, len: usize, last: bool) -> io::Result<()> {
+        let aad = segment_aad(&self.header, self.index, last);
+        let plaintext = self.key.open(&self.buf[..len], &aad)?;
+        self.inner.write_all(&plaintext)?;
+        self.buf.drain(..len);
+        self.index += 1;
+        Ok(())
+    }
+
+    /// 最後のセグメントを復号し、`inner` を返す
+    pub fn finish(mut self) -> io::Result<W> {
+        self.open_segment(self.buf.len(), true)?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for OpenWriter<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(data);
+        while self.buf.len() > SEALED_SEGMENT_SIZE {
+            self.open_segment(SEALED_SEGMENT_SIZE, false)?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KdfParams {
+    salt: String,
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredKey {
+    id: String,
+    created_at: DateTime<Utc>,
+    /// 鍵そのもの。パスフレーズ保護時はnonce + 暗号文
+    key: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyFile {
+    version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    kdf: Option<KdfParams>,
+    keys: Vec<StoredKey>,
+}
+
+/// 鍵ファイルに保存された鍵の一覧（最後の鍵で暗号化し、古い鍵は復号にのみ使う）
+///
+/// 同じ鍵ファイルを共有するメンバー同士は、暗号化したblobも同じ内容になる。
+#[derive(Clone)]
+pub struct Keyring {
+    keys: Vec<DataKey>,
+}
+
+impl Keyring {
+    /// 新しい鍵を1つ持つキーリングを作る
+    pub fn generate() -> Self {
+        Self {
+            keys: vec![DataKey::generate()],
+        }
+    }
+
+    /// 新しい鍵を追加して以後の暗号化に使う。古い鍵は既存データの復号用に残す
+    pub fn rotate(&mut self) {
+        self.keys.push(DataKey::generate());
+    }
+
+    pub fn active(&self) -> &DataKey {
+        self.keys
+            .last()
+            .expect("キーリングには必ず1つ以上の鍵がある")
+    }
+
+    pub fn get(&self, id: &[u8]) -> Result<&DataKey> {
+        self.keys
+            .iter()
+            .find(|k| k.id == id)
+            .ok_or_else(|| GpError::KeyError(format!("鍵 {} がありません", hex::encode(id))))
+    }
+
+    pub fn key_count(&self) -> usize {
+        self.keys.len()
+    }
+
+    /// `GROOVEPUSH_KEY_FILE`、未設定なら `~/.config/groovepush/key.json`
+    pub fn default_path() -> Option<PathBuf> {
+        if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+            return Some(PathBuf::from(path));
+        }
+        std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config/groovepush/key.json"))
+    }
+
+    /// 鍵ファイルがあれば読み込む。無ければ暗号化は無効
+    pub fn load_default() -> Result<Option<Self>> {
+        let Some(path) = Self::default_path().filter(|p| p.exists()) else {
+            return Ok(None);
+        };
+
+        let passphrase = if Self::is_protected(&path)? {
+            Some(read_passphrase("鍵ファイルのパスフレーズ: ")?)
+        } else {
+            None
+        };
+        Self::load(&path, passphrase.as_deref()).map(Some)
+    }
+
+    pub fn is_protected(path: &Path) -> Result<bool> {
+        Ok(read_key_file(path)?.kdf.is_some())
+    }
+
+    pub fn load(path: &Path, passphrase: Option<&str>) -> Result<Self> {
+        let file = read_key_file(path)?;
+
+        let kek = match (&file.kdf, passphrase) {
+            (Some(kdf), Some(passphrase)) => Some(derive_kek(passphrase, kdf)?),
+            (Some(_), None) => {
+                return Err(GpError::KeyError(
+                    "鍵ファイルはパスフレーズで保護されています".to_string(),
+                ))
+            }
+            (None, _) => None,
+        };
+
+        let keys = file
+            .keys
+            .iter()
+            .map(|stored| {
+                let bytes = hex::decode(&stored.key)
+                    .map_err(|e| GpError::KeyError(format!("{}: {}", path.display(), e)))?;
+                let bytes = match &kek {
+                    Some(kek) => unwrap_key(kek, &bytes, stored.id.as_bytes())?,
+                    None => bytes,
+                };
+                let key: [u8; KEY_LEN] = bytes.try_into().map_err(|_| {
+                    GpError::KeyError(format!("{}: 鍵の長さが不正です", path.display()))
+                })?;
+                Ok(DataKey::from_bytes(key, stored.created_at))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        if keys.is_empty() {
+            return Err(GpError::KeyError(format!(
+                "{}: 鍵がありません",
+                path.display()
+            )));
+        }
+        Ok(Self { keys })
+    }
+
+    /// 鍵ファイルを保存する。`passphrase` を指定すると鍵をArgon2idで導出した鍵で暗号化する
+    pub fn save(&self, path: &Path, passphrase: Option<&str>) -> Result<()> {
+        let kdf = passphrase.map(|_| {
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            KdfParams {
+                salt: hex::encode(salt),
+                memory_kib: KDF_MEMORY_KIB,
+                iterations: KDF_ITERATIONS,
+                parallelism: KDF_PARALLELISM,
+            }
+        });
+        let kek = match (&kdf, passphrase) {
+            (Some(kdf), Some(passphrase)) => Some(derive_kek(passphrase, kdf)?),
+            _ => None,
+        };
+
+        let keys = self
+            .keys
+            .iter()
+            .map(|k| {
+                let id = hex::encode(k.id);
+                let key = match &kek {
+                    Some(kek) => wrap_key(kek, &k.key, id.as_bytes()),
+                    None => k.key.to_vec(),
+                };
+                StoredKey {
+                    id,
+                    created_at: k.created_at,
+                    key: hex::encode(key),
+                }
+            })
+            .collect();
+
+        let file = KeyFile {
+            version: KEY_FILE_VERSION,
+            kdf,
+            keys,
+        };
+        let body =
+            serde_json::to_vec_pretty(&file).map_err(|e| GpError::KeyError(e.to_string()))?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        write_private(&tmp, &body)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+fn read_key_file(path: &Path) -> Result<KeyFile> {
+    let bytes = fs::read(path)?;
+    let file: KeyFile = serde_json::from_slice(&bytes)
+        .map_err(|e| GpError::KeyError(format!("{}: {}", path.display(), e)))?;
+    if file.version != KEY_FILE_VERSION {
+        return Err(GpError::KeyError(format!(
+            "{}: 未対応の鍵ファイル形式: v{}",
+            path.display(),
+            file.version
+        )));
+    }
+    Ok(file)
+}
+
+/// 鍵ファイルは所有者のみ読み書きできるようにする
+#[cfg(unix)]
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(data)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn write_private(path: &Path, data: &[u8]) -> Result<()> {
+    fs::write(path, data)?;
+    Ok(())
+}
+
+fn derive_kek(passphrase: &str, kdf: &KdfParams) -> Result<[u8; KEY_LEN]> {
+    let salt = hex::decode(&kdf.salt).map_err(|e| GpError::KeyError(e.to_string()))?;
+    let params = argon2::Params::new(
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(KEY_LEN),
+    )
+    .map_err(|e| GpError::KeyError(e.to_string()))?;
+    let argon2 = argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params);
+
+    let mut kek = [0u8; KEY_LEN];
+    argon2
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut kek)
+        .map_err(|e| GpError::KeyError(e.to_string()))?;
+    Ok(kek)
+}
+
+fn wrap_key(kek: &[u8; KEY_LEN], key: &[u8], aad: &[u8]) -> Vec<u8> {
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(kek.into())
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: key, aad })
+        .expect("AES-GCMの暗号化は失敗しない");
+
+    let mut wrapped = nonce.to_vec();
+    wrapped.extend_from_slice(&ciphertext);
+    wrapped
+}
+
+fn unwrap_key(kek: &[u8; KEY_LEN], wrapped: &[u8], aad: &[u8]) -> Result<Vec<u8>> {
+    if wrapped.len() < NONCE_LEN {
+        return Err(GpError::KeyError("鍵ファイルが壊れています".to_string()));
+    }
+    let (nonce, ciphertext) = wrapped.split_at(NONCE_LEN);
+    Aes256Gcm::new(kek.into())
+        .decrypt(
+            Nonce::from_slice(nonce),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| GpError::KeyError("パスフレーズが違います".to_string()))
+}
+
+/// `GROOVEPUSH_PASSPHRASE` 環境変数、未設定なら端末から入力する
+pub fn read_passphrase(prompt: &str) -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+    Ok(rpassword::prompt_password(prompt)?)
+}
+
+/// 新しいパスフレーズを確認付きで入力する
+pub fn read_new_passphrase() -> Result<String> {
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Ok(passphrase);
+    }
+
+    let passphrase = rpassword::prompt_password("新しいパスフレーズ: ")?;
+    if passphrase.is_empty() {
+        return Err(GpError::KeyError("パスフレーズが空です".to_string()));
+    }
+    if rpassword::prompt_password("確認のためもう一度入力: ")? != passphrase {
+        return Err(GpError::KeyError("パスフレーズが一致しません".to_string()));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seal_all(key: &DataKey, data: &[u8]) -> Vec<u8> {
+        let mut writer = SealWriter::new(Vec::new(), key, b"hdr");
+        writer.write_all(data).unwrap();
+        writer.finish().unwrap()
+    }
+
+    fn open_all(key: &DataKey, sealed: &[u8], feed: usize) -> io::Result<Vec<u8>> {
+        let mut writer = OpenWriter::new(Vec::new(), key, b"hdr");
+        for chunk in sealed.chunks(feed) {
+            writer.write_all(chunk)?;
+        }
+        writer.finish()
+    }
+
+    #[test]
+    fn test_seal_roundtrip_across_segments() {
+        let key = DataKey::generate();
+        for len in [0, 5, SEGMENT_SIZE, SEGMENT_SIZE * 2 + 3] {
+            let data: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+            let sealed = seal_all(&key, &data);
+            assert_eq!(open_all(&key, &sealed, 64 * 1024).unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn test_seal_is_deterministic_per_key() {
+        let key = DataKey::generate();
+        let data = b"unreleased master".repeat(100);
+        assert_eq!(seal_all(&key, &data), seal_all(&key, &data));
+        assert_ne!(seal_all(&key, &data), seal_all(&DataKey::generate(), &data));
+    }
+
+    #[test]
+    fn test_open_rejects_tampering_and_truncation() {
+        let key = DataKey::generate();
+        let data = vec![7u8; SEGMENT_SIZE + 10];
+        let sealed = seal_all(&key, &data);
+
+        let mut tampered = sealed.clone();
+        tampered[NONCE_LEN + 3] ^= 1;
+        assert!(open_all(&key, &tampered, 4096).is_err());
+
+        assert!(open_all(&key, &sealed[..SEALED_SEGMENT_SIZE], 4096).is_err());
+        assert!(open_all(&DataKey::generate(), &sealed, 4096).is_err());
+    }
+
+    #[test]
+    fn test_key_file_roundtrip_with_passphrase() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key.json");
+
+        let mut keyring = Keyring::generate();
+        keyring.rotate();
+        keyring.save(&path, Some("correct horse")).unwrap();
+
+        assert!(Keyring::is_protected(&path).unwrap());
+        assert!(Keyring::load(&path, None).is_err());
+        assert!(Keyring::load(&path, Some("wrong")).is_err());
+
+        let loaded = Keyring::load(&path, Some("correct horse")).unwrap();
+        assert_eq!(loaded.key_count(), 2);
+        assert_eq!(loaded.active().id(), keyring.active().id());
+        assert!(loaded.get(keyring.keys[0].id()).is_ok());
+    }
+}
@@ -8,11 +8,38 @@ pub enum GpError {
     #[error("S3エラー: {0}")]
     S3Error(String),
 
+    #[error("ストレージエラー: {0}")]
+    StorageError(String),
+
     #[error("IOエラー: {0}")]
     IoError(#[from] std::io::Error),
 
     #[error("blobが見つかりません: {0}")]
     BlobNotFound(String),
+
+    #[error("blobのハッシュが一致しません: {expected}（実際: {actual}）")]
+    HashMismatch { expected: String, actual: String },
+
+    #[error("blobが破損しています: {0}")]
+    CorruptBlob(String),
+
+    #[error("履歴が他のプッシュによって更新されました")]
+    HistoryConflict,
+
+    #[error("{0} がプロジェクトをロックしています")]
+    ProjectLocked(String),
+
+    #[error("無効なパターン: {0}")]
+    InvalidPattern(String),
+
+    #[error("無効なリモート: {0}")]
+    InvalidRemote(String),
+
+    #[error("設定エラー: {0}")]
+    ConfigError(String),
+
+    #[error("鍵エラー: {0}")]
+    KeyError(String),
 }
 
 pub type Result<T> = std::result::Result<T, GpError>;
@@ -0,0 +1,218 @@
+use crate::error::{GpError, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+const INDEX_VERSION: u32 = 1;
+
+/// ハッシュ計算時点のファイルのメタデータ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexEntry {
+    pub size: u64,
+    pub mtime_sec: i64,
+    pub mtime_nsec: u32,
+    pub inode: u64,
+    pub hash: String,
+}
+
+impl IndexEntry {
+    pub fn new(metadata: &Metadata, hash: String) -> Self {
+        let (mtime_sec, mtime_nsec) = mtime_of(metadata);
+        Self {
+            size: metadata.len(),
+            mtime_sec,
+            mtime_nsec,
+            inode: inode_of(metadata),
+            hash,
+        }
+    }
+
+    fn matches(&self, metadata: &Metadata) -> bool {
+        let (mtime_sec, mtime_nsec) = mtime_of(metadata);
+        self.size == metadata.len()
+            && self.mtime_sec == mtime_sec
+            && self.mtime_nsec == mtime_nsec
+            && self.inode == inode_of(metadata)
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct IndexData {
+    version: u32,
+    /// 保存時刻。これ以降に更新されたファイルはmtimeが同じでも内容が変わりうる
+    written_sec: i64,
+    written_nsec: u32,
+    entries: HashMap<String, IndexEntry>,
+}
+
+/// gitのインデックスに相当するハッシュキャッシュ（`.gp/index.json`）
+///
+/// 相対パスごとにサイズ・mtime・inode・ハッシュを記録し、
+/// メタデータが変わっていないファイルは再ハッシュを省略する。
+pub struct HashIndex {
+    path: PathBuf,
+    data: IndexData,
+}
+
+impl HashIndex {
+    /// インデックスを読み込む。存在しない・壊れている場合は空として扱う
+    pub fn load(gp_dir: &Path) -> Self {
+        let path = gp_dir.join(INDEX_FILE);
+        let data = fs::read(&path)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<IndexData>(&bytes).ok())
+            .filter(|d| d.version == INDEX_VERSION)
+            .unwrap_or_default();
+
+        Self { path, data }
+    }
+
+    /// 空のインデックス（`--rehash` 用）
+    pub fn empty(gp_dir: &Path) -> Self {
+        Self {
+            path: gp_dir.join(INDEX_FILE),
+            data: IndexData::default(),
+        }
+    }
+
+    /// メタデータが一致するならキャッシュ済みのハッシュを返す
+    pub fn lookup(&self, relative_path: &str, metadata: &Metadata) -> Option<&str> {
+        let entry = self.data.entries.get(relative_path)?;
+
+        if !entry.matches(metadata) {
+            return None;
+        }
+
+        // 前回の保存と同じ時刻以降に更新されたファイルは、保存後に書き換えられた可能性がある
+        let written = (self.data.written_sec, self.data.written_nsec);
+        if (entry.mtime_sec, entry.mtime_nsec) >= written {
+            return None;
+        }
+
+        Some(&entry.hash)
+    }
+
+    /// 今回のスキャン結果でインデックスを置き換えて保存する
+    pub fn save(mut self, entries: HashMap<String, IndexEntry>) -> Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        self.data = IndexData {
+            version: INDEX_VERSION,
+            written_sec: now.as_secs() as i64,
+            written_nsec: now.subsec_nanos(),
+            entries,
+        };
+
+        let body =
+            serde_json::to_vec(&self.data).map_err(|e| GpError::StorageError(e.to_string()))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+fn mtime_of(metadata: &Metadata) -> (i64, u32) {
+    match metadata
+        .modified()
+        .ok()
+        .map(|t| t.duration_since(UNIX_EPOCH))
+    {
+        Some(Ok(d)) => (d.as_secs() as i64, d.subsec_nanos()),
+        _ => (0, 0),
+    }
+}
+
+#[cfg(unix)]
+fn inode_of(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode_of(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn set_mtime(path: &Path, time: SystemTime) {
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lookup_hits_for_unchanged_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("kick.wav");
+        fs::write(&file, b"kick").unwrap();
+        set_mtime(&file, SystemTime::now() - Duration::from_secs(60));
+
+        let metadata = fs::metadata(&file).unwrap();
+        let mut entries = HashMap::new();
+        entries.insert(
+            "kick.wav".to_string(),
+            IndexEntry::new(&metadata, "abc".to_string()),
+        );
+        HashIndex::empty(dir.path()).save(entries).unwrap();
+
+        let index = HashIndex::load(dir.path());
+        assert_eq!(index.lookup("kick.wav", &metadata), Some("abc"));
+        assert_eq!(index.lookup("snare.wav", &metadata), None);
+    }
+
+    #[test]
+    fn test_lookup_misses_when_metadata_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("kick.wav");
+        fs::write(&file, b"kick").unwrap();
+        set_mtime(&file, SystemTime::now() - Duration::from_secs(60));
+
+        let metadata = fs::metadata(&file).unwrap();
+        let mut entries = HashMap::new();
+        entries.insert(
+            "kick.wav".to_string(),
+            IndexEntry::new(&metadata, "abc".to_string()),
+        );
+        HashIndex::empty(dir.path()).save(entries).unwrap();
+
+        fs::write(&file, b"kick 2").unwrap();
+        let changed = fs::metadata(&file).unwrap();
+        assert_eq!(
+            HashIndex::load(dir.path()).lookup("kick.wav", &changed),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lookup_misses_for_racily_clean_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("kick.wav");
+        fs::write(&file, b"kick").unwrap();
+        set_mtime(&file, SystemTime::now() + Duration::from_secs(60));
+
+        let metadata = fs::metadata(&file).unwrap();
+        let mut entries = HashMap::new();
+        entries.insert(
+            "kick.wav".to_string(),
+            IndexEntry::new(&metadata, "abc".to_string()),
+        );
+        HashIndex::empty(dir.path()).save(entries).unwrap();
+
+        assert_eq!(
+            HashIndex::load(dir.path()).lookup("kick.wav", &metadata),
+            None
+        );
+    }
+}
@@ -1,13 +1,18 @@
+mod chunker;
 mod cli;
 mod commands;
+mod config;
+mod crypto;
 mod error;
+mod index;
 mod scanner;
 mod storage;
 mod utils;
+mod workspace;
 
 use anyhow::Result;
 use clap::Parser;
-use cli::{Cli, Commands};
+use cli::{Cli, Commands, KeyCommands, RemoteCommands};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -15,24 +20,205 @@ async fn main() -> Result<()> {
     let current_dir = std::env::current_dir()?;
 
     match cli.command {
-        Commands::Push { message, dry_run } => {
-            commands::push::run(&current_dir, message.as_deref(), dry_run).await?;
+        Commands::Push {
+            message,
+            dry_run,
+            rehash,
+            remote,
+        } => {
+            let storage = storage::open(&current_dir, remote.as_deref()).await?;
+            commands::push::run(
+                storage.as_ref(),
+                &current_dir,
+                message.as_deref(),
+                dry_run,
+                rehash,
+            )
+            .await?;
         }
-        Commands::Log { project, limit } => {
-            commands::log::run(project.as_deref(), limit).await?;
+        Commands::Pull { force, remote } => {
+            let storage = storage::open(&current_dir, remote.as_deref()).await?;
+            commands::pull::run(storage.as_ref(), &current_dir, force).await?;
         }
-        Commands::Checkout { snapshot, output } => {
-            commands::checkout::run(&snapshot, output.as_deref()).await?;
+        Commands::Log {
+            project,
+            branch,
+            limit,
+            remote,
+        } => {
+            let storage = storage::open(&current_dir, remote.as_deref()).await?;
+            commands::log::run(storage.as_ref(), project.as_deref(), branch.as_deref(), limit)
+                .await?;
         }
-        Commands::Init => {
-            commands::init::run(&current_dir)?;
+        Commands::Branch {
+            name,
+            snapshot,
+            delete,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            match name {
+                Some(name) if delete => {
+                    commands::branch::run_delete(storage.as_ref(), &current_dir, &name).await?;
+                }
+                Some(name) => {
+                    commands::branch::run_create(
+                        storage.as_ref(),
+                        &current_dir,
+                        &name,
+                        snapshot.as_deref(),
+                    )
+                    .await?;
+                }
+                None => {
+                    commands::branch::run_list(storage.as_ref(), &current_dir).await?;
+                }
+            }
         }
-        Commands::Status => {
-            commands::status::run(&current_dir).await?;
+        Commands::Switch { branch, force } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::switch::run(storage.as_ref(), &current_dir, &branch, force).await?;
         }
-        Commands::Clone { project } => {
-            commands::clone::run(&project, &current_dir).await?;
+        Commands::Tag {
+            name,
+            snapshot,
+            delete,
+            force,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            match name {
+                Some(name) if delete => {
+                    commands::tag::run_delete(storage.as_ref(), &current_dir, &name).await?;
+                }
+                name => {
+                    commands::tag::run(
+                        storage.as_ref(),
+                        &current_dir,
+                        name.as_deref(),
+                        snapshot.as_deref(),
+                        force,
+                    )
+                    .await?;
+                }
+            }
         }
+        Commands::Diff {
+            from,
+            to,
+            summary,
+            json,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::diff::run(
+                storage.as_ref(),
+                &current_dir,
+                from.as_deref(),
+                to.as_deref(),
+                summary,
+                json,
+            )
+            .await?;
+        }
+        Commands::Checkout {
+            snapshot,
+            output,
+            project,
+            force,
+            remote,
+        } => {
+            let storage = storage::open(&current_dir, remote.as_deref()).await?;
+            commands::checkout::run(
+                storage.as_ref(),
+                &current_dir,
+                project.as_deref(),
+                &snapshot,
+                output.as_deref(),
+                remote.as_deref(),
+                force,
+            )
+            .await?;
+        }
+        Commands::Restore {
+            snapshot,
+            paths,
+            output,
+            force,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::restore::run(
+                storage.as_ref(),
+                &current_dir,
+                &snapshot,
+                &paths,
+                output.as_deref(),
+                force,
+            )
+            .await?;
+        }
+        Commands::Init { name, remote } => {
+            commands::init::run(&current_dir, name.as_deref(), remote.as_deref())?;
+        }
+        Commands::Status { rehash } => {
+            let keyring = crypto::Keyring::load_default()?;
+            let storage = storage::open_with_keyring(&current_dir, None, keyring.clone()).await?;
+            commands::status::run(storage.as_ref(), keyring, &current_dir, rehash).await?;
+        }
+        Commands::Clone { project, remote } => {
+            let storage = storage::open(&current_dir, remote.as_deref()).await?;
+            commands::clone::run(storage.as_ref(), &project, &current_dir, remote.as_deref())
+                .await?;
+        }
+        Commands::Verify { snapshot } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::verify::run(storage.as_ref(), &current_dir, snapshot.as_deref()).await?;
+        }
+        Commands::Fsck { quick, repair } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::fsck::run(storage.as_ref(), &current_dir, quick, repair).await?;
+        }
+        Commands::Gc {
+            dry_run,
+            grace_hours,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::gc::run(storage.as_ref(), &current_dir, dry_run, grace_hours).await?;
+        }
+        Commands::Prune {
+            dry_run,
+            grace_hours,
+        } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::prune::run(storage.as_ref(), &current_dir, dry_run, grace_hours).await?;
+        }
+        Commands::Lock { force } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::lock::run_lock(storage.as_ref(), &current_dir, force).await?;
+        }
+        Commands::Unlock { force } => {
+            let storage = storage::open_default(&current_dir).await?;
+            commands::lock::run_unlock(storage.as_ref(), &current_dir, force).await?;
+        }
+        Commands::Key { command } => match command {
+            KeyCommands::New { output, passphrase } => {
+                commands::key::run_new(output.as_deref(), passphrase)?;
+            }
+            KeyCommands::Rotate { passphrase } => {
+                commands::key::run_rotate(passphrase)?;
+            }
+        },
+        Commands::Remote { command } => match command {
+            RemoteCommands::Add { name, url, default } => {
+                commands::remote::run_add(&current_dir, &name, &url, default)?;
+            }
+            RemoteCommands::Remove { name } => {
+                commands::remote::run_remove(&current_dir, &name)?;
+            }
+            RemoteCommands::List => {
+                commands::remote::run_list(&current_dir)?;
+            }
+            RemoteCommands::Default { name } => {
+                commands::remote::run_default(&current_dir, &name)?;
+            }
+        },
     }
 
     Ok(())
@@ -1,12 +1,18 @@
 use crate::error::{GpError, Result};
-use ignore::WalkBuilder;
+use crate::index::{HashIndex, IndexEntry};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use serde::Serialize;
 use sha2::{Digest, Sha256};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
 
 const GP_IGNORE_FILE: &str = ".gp-ignore";
 const GP_DIR: &str = ".gp";
+const HASH_BUFFER_SIZE: usize = 1024 * 1024;
 
 /// DAWの一時ファイルなど、デフォルトで除外するパターン
 const DEFAULT_IGNORES: &[&str] = &[
@@ -25,6 +31,8 @@ const DEFAULT_IGNORES: &[&str] = &[
     "*.swp",
     // GroovePush管理フォルダ
     ".gp/",
+    // 中断したダウンロードの一時ファイル
+    "*.gp-download",
 ];
 
 #[derive(Debug, Clone)]
@@ -37,7 +45,8 @@ pub struct ScannedFile {
 
 pub struct Scanner {
     root: PathBuf,
-    ignore_patterns: Vec<String>,
+    default_ignores: Gitignore,
+    rehash: bool,
 }
 
 impl Scanner {
@@ -48,30 +57,32 @@ impl Scanner {
             return Err(GpError::DirectoryNotFound(root.display().to_string()));
         }
 
-        let ignore_patterns = Self::load_ignore_patterns(&root);
+        let default_ignores = Self::default_ignores(&root)?;
 
         Ok(Self {
             root,
-            ignore_patterns,
+            default_ignores,
+            rehash: false,
         })
     }
 
-    fn load_ignore_patterns(root: &Path) -> Vec<String> {
-        let mut patterns: Vec<String> = DEFAULT_IGNORES.iter().map(|s| s.to_string()).collect();
+    /// `.gp/index.json` のキャッシュを使わず、全ファイルを再ハッシュする
+    pub fn with_rehash(mut self, rehash: bool) -> Self {
+        self.rehash = rehash;
+        self
+    }
 
-        let ignore_file = root.join(GP_IGNORE_FILE);
-        if ignore_file.exists() {
-            if let Ok(content) = fs::read_to_string(&ignore_file) {
-                for line in content.lines() {
-                    let line = line.trim();
-                    if !line.is_empty() && !line.starts_with('#') {
-                        patterns.push(line.to_string());
-                    }
-                }
-            }
+    /// `.gp-ignore` と同じ書式で `DEFAULT_IGNORES` を解釈する
+    fn default_ignores(root: &Path) -> Result<Gitignore> {
+        let mut builder = GitignoreBuilder::new(root);
+        for pattern in DEFAULT_IGNORES {
+            builder
+                .add_line(None, pattern)
+                .map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?;
         }
-
-        patterns
+        builder
+            .build()
+            .map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))
     }
 
     pub fn scan(&self) -> Result<Vec<ScannedFile>> {
@@ -83,56 +94,122 @@ impl Scanner {
             .git_global(false)
             .git_exclude(false);
 
-        for pattern in &self.ignore_patterns {
-            builder.add_ignore(pattern);
-        }
+        let default_ignores = self.default_ignores.clone();
+        builder.filter_entry(move |entry| {
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !default_ignores.matched(entry.path(), is_dir).is_ignore()
+        });
 
         let ignore_file = self.root.join(GP_IGNORE_FILE);
         if ignore_file.exists() {
             builder.add_ignore(&ignore_file);
         }
 
-        let mut files = Vec::new();
         let gp_dir = self.root.join(GP_DIR);
 
-        for entry in builder.build() {
-            let entry = entry.map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?;
-            let path = entry.path();
+        let index = if self.rehash {
+            HashIndex::empty(&gp_dir)
+        } else {
+            HashIndex::load(&gp_dir)
+        };
 
-            if !path.is_file() {
-                continue;
-            }
+        let results = Mutex::new(Vec::new());
+        let first_error = Mutex::new(None);
 
-            if path.starts_with(&gp_dir) {
-                continue;
-            }
+        // ワーカースレッドごとに走査とハッシュ計算を並列に行う
+        builder.threads(0).build_parallel().run(|| {
+            Box::new(|entry| match self.visit(entry, &gp_dir, &index) {
+                Ok(Some(found)) => {
+                    lock(&results).push(found);
+                    WalkState::Continue
+                }
+                Ok(None) => WalkState::Continue,
+                Err(e) => {
+                    lock(&first_error).get_or_insert(e);
+                    WalkState::Quit
+                }
+            })
+        });
 
-            let relative_path = path
-                .strip_prefix(&self.root)
-                .map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?
-                .to_path_buf();
+        if let Some(e) = lock(&first_error).take() {
+            return Err(e);
+        }
 
-            let metadata = fs::metadata(path)?;
-            let hash = self.compute_hash(path)?;
+        let mut files = Vec::new();
+        let mut entries = HashMap::new();
+        for (file, entry) in results.into_inner().unwrap_or_else(|e| e.into_inner()) {
+            entries.insert(file.relative_path.to_string_lossy().to_string(), entry);
+            files.push(file);
+        }
+        files.sort_by(|a, b| a.relative_path.cmp(&b.relative_path));
 
-            files.push(ScannedFile {
-                relative_path,
-                absolute_path: path.to_path_buf(),
-                size: metadata.len(),
-                hash,
-            });
+        if gp_dir.is_dir() {
+            index.save(entries)?;
         }
 
         Ok(files)
     }
 
-    fn compute_hash(&self, path: &Path) -> Result<String> {
-        let content = fs::read(path)?;
-        let mut hasher = Sha256::new();
-        hasher.update(&content);
-        let result = hasher.finalize();
-        Ok(format!("{:x}", result))
+    fn visit(
+        &self,
+        entry: std::result::Result<DirEntry, ignore::Error>,
+        gp_dir: &Path,
+        index: &HashIndex,
+    ) -> Result<Option<(ScannedFile, IndexEntry)>> {
+        let entry = entry.map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?;
+        let path = entry.path();
+
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        if path.starts_with(gp_dir) {
+            return Ok(None);
+        }
+
+        let relative_path = path
+            .strip_prefix(&self.root)
+            .map_err(|e| GpError::IoError(std::io::Error::other(e.to_string())))?
+            .to_path_buf();
+
+        let metadata = fs::metadata(path)?;
+        let key = relative_path.to_string_lossy().to_string();
+        let hash = match index.lookup(&key, &metadata) {
+            Some(hash) => hash.to_string(),
+            None => hash_file(path)?,
+        };
+
+        let index_entry = IndexEntry::new(&metadata, hash.clone());
+        let file = ScannedFile {
+            relative_path,
+            absolute_path: path.to_path_buf(),
+            size: metadata.len(),
+            hash,
+        };
+
+        Ok(Some((file, index_entry)))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// ファイルを固定長バッファで読みながらSHA256を計算する
+pub fn hash_file(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; HASH_BUFFER_SIZE];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
     }
+
+    Ok(format!("{:x}", hasher.finalize()))
 }
 
 /// 2つのファイルリストを比較し、変更されたファイルを検出
@@ -152,3 +229,239 @@ pub fn diff_files(
         .cloned()
         .collect()
 }
+
+/// 比較するファイルの内容（サイズが不明な場合は0）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileEntry {
+    pub hash: String,
+    pub size: u64,
+}
+
+/// 2つのファイル一覧の間の1ファイル分の違い
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileChange {
+    Added {
+        path: String,
+        size: u64,
+    },
+    Removed {
+        path: String,
+        size: u64,
+    },
+    Modified {
+        path: String,
+        old_size: u64,
+        new_size: u64,
+    },
+    /// 内容を変えずに移動・名前変更されたファイル
+    Renamed {
+        from: String,
+        to: String,
+        size: u64,
+    },
+}
+
+impl FileChange {
+    /// 容量の増減（バイト）
+    pub fn size_delta(&self) -> i64 {
+        match self {
+            Self::Added { size, .. } => *size as i64,
+            Self::Removed { size, .. } => -(*size as i64),
+            Self::Modified {
+                old_size, new_size, ..
+            } => *new_size as i64 - *old_size as i64,
+            Self::Renamed { .. } => 0,
+        }
+    }
+
+    /// 並び順に使うパス（名前変更は変更後のパス）
+    fn sort_key(&self) -> &str {
+        match self {
+            Self::Added { path, .. } | Self::Removed { path, .. } | Self::Modified { path, .. } => {
+                path
+            }
+            Self::Renamed { to, .. } => to,
+        }
+    }
+}
+
+/// スキャン結果を比較用のファイル一覧（パス → 内容）にする
+pub fn file_entries(files: &[ScannedFile]) -> BTreeMap<String, FileEntry> {
+    files
+        .iter()
+        .map(|file| {
+            let entry = FileEntry {
+                hash: file.hash.clone(),
+                size: file.size,
+            };
+            (file.relative_path.to_string_lossy().to_string(), entry)
+        })
+        .collect()
+}
+
+/// 2つのファイル一覧（パス → 内容）を比較し、追加・削除・変更・名前変更を検出
+///
+/// `diff_files` と同じくハッシュで比較する。削除されたファイルと同じ内容のファイルが
+/// 別のパスに追加されていれば名前変更とみなす。結果はパス順。
+pub fn compare_files(
+    old: &BTreeMap<String, FileEntry>,
+    new: &BTreeMap<String, FileEntry>,
+) -> Vec<FileChange> {
+    let mut changes = Vec::new();
+    let mut removed: BTreeMap<&str, &FileEntry> = BTreeMap::new();
+    let mut added: BTreeMap<&str, &FileEntry> = BTreeMap::new();
+
+    for (path, entry) in new {
+        match old.get(path) {
+            Some(old_entry) if old_entry.hash == entry.hash => {}
+            Some(old_entry) => changes.push(FileChange::Modified {
+                path: path.clone(),
+                old_size: old_entry.size,
+                new_size: entry.size,
+            }),
+            None => {
+                added.insert(path, entry);
+            }
+        }
+    }
+    for (path, entry) in old {
+        if !new.contains_key(path) {
+            removed.insert(path, entry);
+        }
+    }
+
+    for (from, entry) in removed {
+        let renamed_to = added
+            .iter()
+            .find(|(_, added_entry)| added_entry.hash == entry.hash)
+            .map(|(to, _)| *to);
+        match renamed_to {
+            Some(to) => {
+                added.remove(to);
+                changes.push(FileChange::Renamed {
+                    from: from.to_string(),
+                    to: to.to_string(),
+                    size: entry.size,
+                });
+            }
+            None => changes.push(FileChange::Removed {
+                path: from.to_string(),
+                size: entry.size,
+            }),
+        }
+    }
+    for (path, entry) in added {
+        changes.push(FileChange::Added {
+            path: path.to_string(),
+            size: entry.size,
+        });
+    }
+
+    changes.sort_by(|a, b| a.sort_key().cmp(b.sort_key()));
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file_streams_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc.txt");
+        fs::write(&path, b"abc").unwrap();
+
+        assert_eq!(
+            hash_file(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_scan_is_sorted_and_skips_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Samples")).unwrap();
+        fs::create_dir_all(dir.path().join(".gp")).unwrap();
+        fs::write(dir.path().join("Song.als"), b"song").unwrap();
+        fs::write(dir.path().join("Samples/kick.wav"), b"kick").unwrap();
+        fs::write(dir.path().join("Samples/kick.asd"), b"analysis").unwrap();
+        fs::write(dir.path().join(".gp/history.json"), b"{}").unwrap();
+        fs::write(dir.path().join(".gp-ignore"), b"*.wav.bak\n").unwrap();
+        fs::write(dir.path().join("Samples/kick.wav.bak"), b"old kick").unwrap();
+        fs::write(dir.path().join(".Song.als.gp-download"), b"so").unwrap();
+        fs::create_dir_all(dir.path().join("Backup")).unwrap();
+        fs::write(dir.path().join("Backup/Song [2024].als"), b"old song").unwrap();
+
+        let files = Scanner::new(dir.path()).unwrap().scan().unwrap();
+        let paths: Vec<_> = files.iter().map(|f| f.relative_path.clone()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                PathBuf::from(".gp-ignore"),
+                PathBuf::from("Samples/kick.wav"),
+                PathBuf::from("Song.als")
+            ]
+        );
+        assert!(dir.path().join(".gp/index.json").exists());
+
+        let rescanned = Scanner::new(dir.path()).unwrap().scan().unwrap();
+        assert_eq!(files[0].hash, rescanned[0].hash);
+    }
+
+    #[test]
+    fn test_compare_files_detects_renames() {
+        let entries = |files: &[(&str, &str, u64)]| {
+            files
+                .iter()
+                .map(|(path, hash, size)| {
+                    (
+                        path.to_string(),
+                        FileEntry {
+                            hash: hash.to_string(),
+                            size: *size,
+                        },
+                    )
+                })
+                .collect::<BTreeMap<_, _>>()
+        };
+        let old = entries(&[
+            ("Song.als", "s1", 100),
+            ("kick.wav", "k1", 50),
+            ("old.wav", "o1", 30),
+            ("same.wav", "x1", 10),
+        ]);
+        let new = entries(&[
+            ("Song.als", "s2", 120),
+            ("Samples/kick.wav", "k1", 50),
+            ("same.wav", "x1", 10),
+            ("vox.wav", "v1", 70),
+        ]);
+
+        let changes = compare_files(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                FileChange::Renamed {
+                    from: "kick.wav".to_string(),
+                    to: "Samples/kick.wav".to_string(),
+                    size: 50,
+                },
+                FileChange::Modified {
+                    path: "Song.als".to_string(),
+                    old_size: 100,
+                    new_size: 120,
+                },
+                FileChange::Removed {
+                    path: "old.wav".to_string(),
+                    size: 30,
+                },
+                FileChange::Added {
+                    path: "vox.wav".to_string(),
+                    size: 70,
+                },
+            ]
+        );
+        assert_eq!(changes.iter().map(FileChange::size_delta).sum::<i64>(), 60);
+    }
+}
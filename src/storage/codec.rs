@@ -0,0 +1,810 @@
+use crate::crypto::{DataKey, Keyring, OpenWriter, SealWriter, KEY_ID_LEN};
+use crate::error::{GpError, Result};
+use crate::storage::journal::EncodedForm;
+use crate::storage::{BlobUpload, DownloadTarget};
+use indicatif::ProgressBar;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// エンコード済みblobの先頭に付くヘッダ: MAGIC(7) + バージョン(1) + コーデック(1) + 暗号方式(1)
+///
+/// 暗号化されている場合はさらに鍵ID(8)が続く。ヘッダの無いblobは従来どおり生データとして扱う。
+const MAGIC: &[u8; 7] = b"\x89GPBLOB";
+const FORMAT_VERSION: u8 = 1;
+const HEADER_LEN: usize = 10;
+
+const CIPHER_NONE: u8 = 0;
+const CIPHER_AES_GCM: u8 = 1;
+
+const ZSTD_LEVEL: i32 = 3;
+
+/// 圧縮がよく効くプロジェクトファイル・MIDI・プリセット等の拡張子
+#[rustfmt::skip]
+const COMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    // DAWのプロジェクトファイル
+    "als", "alc", "rpp", "rpp-bak", "flp", "cpr", "song", "ptx",
+    // MIDI
+    "mid", "midi",
+    // プラグイン・デバイスのプリセット
+    "adg", "adv", "fxp", "fxb", "vstpreset", "aupreset", "nmsv", "h2p",
+    // テキスト系
+    "txt", "xml", "json", "csv",
+];
+
+/// blobの圧縮方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    None,
+    Zstd,
+}
+
+impl Codec {
+    fn id(self) -> u8 {
+        match self {
+            Codec::None => 0,
+            Codec::Zstd => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self> {
+        match id {
+            0 => Ok(Codec::None),
+            1 => Ok(Codec::Zstd),
+            _ => Err(GpError::StorageError(format!("未対応のコーデック: {}", id))),
+        }
+    }
+
+    /// 有効にしたコーデックを、圧縮が効く種類のファイルにだけ適用する
+    ///
+    /// WAVや既に圧縮済みの音声（MP3/FLAC等）はそのまま保存する。
+    pub fn for_path(self, path: &Path) -> Self {
+        let compressible = path
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .is_some_and(|e| COMPRESSIBLE_EXTENSIONS.contains(&e.as_str()));
+
+        if compressible {
+            self
+        } else {
+            Codec::None
+        }
+    }
+}
+
+impl FromStr for Codec {
+    type Err = GpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Codec::None),
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(GpError::StorageError(format!("未対応のコーデック: {}", s))),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::None => write!(f, "none"),
+            Codec::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// アップロード時のblobのエンコード方法（圧縮方式・暗号化鍵・一時ファイルの置き場所）
+#[derive(Clone)]
+pub struct BlobEncoding {
+    codec: Codec,
+    keyring: Option<Keyring>,
+    staging_dir: PathBuf,
+}
+
+impl BlobEncoding {
+    pub fn new(codec: Codec, keyring: Option<&Keyring>, staging_dir: &Path) -> Self {
+        Self {
+            codec,
+            keyring: keyring.cloned(),
+            staging_dir: staging_dir.to_path_buf(),
+        }
+    }
+
+    /// blobを `staging_dir` の一時ファイルにエンコードし、アップロード元をその一時ファイルに置き換える
+    ///
+    /// 圧縮・暗号化はブロッキング用スレッドで行う。
+    /// 暗号化しない場合、圧縮しても小さくならないblobは生データのまま送る。
+    pub async fn stage(&self, blob: &BlobUpload) -> Result<StagedBlob> {
+        let encoding = self.clone();
+        let blob = blob.clone();
+        tokio::task::spawn_blocking(move || encoding.stage_blocking(blob))
+            .await
+            .map_err(|e| GpError::StorageError(e.to_string()))?
+    }
+
+    fn stage_blocking(&self, blob: BlobUpload) -> Result<StagedBlob> {
+        let codec = self.codec.for_path(&blob.path);
+        let keyring = self.keyring.as_ref();
+        if codec == Codec::None && keyring.is_none() {
+            return Ok(StagedBlob::source(blob));
+        }
+
+        fs::create_dir_all(&self.staging_dir)?;
+        let dest = staging_path(&self.staging_dir, &blob.hash);
+        let mut staged = StagedBlob {
+            upload: BlobUpload {
+                hash: blob.hash.clone(),
+                path: dest.clone(),
+                offset: 0,
+                size: 0,
+            },
+            form: EncodedForm {
+                size: 0,
+                codec: codec.to_string(),
+                key_id: keyring.map(|k| k.active().id_hex()),
+            },
+            temp: Some(dest),
+        };
+        let mut encoded_size = encode(&blob, codec, keyring, &staged.upload.path)?;
+
+        if codec != Codec::None && encoded_size >= blob.size {
+            if keyring.is_none() {
+                return Ok(StagedBlob::source(blob));
+            }
+            encoded_size = encode(&blob, Codec::None, keyring, &staged.upload.path)?;
+            staged.form.codec = Codec::None.to_string();
+        }
+
+        staged.upload.size = encoded_size;
+        staged.form.size = encoded_size;
+        Ok(staged)
+    }
+}
+
+/// エンコード済みのアップロード元。一時ファイルはdropで削除する
+pub struct StagedBlob {
+    pub upload: BlobUpload,
+    /// 中断したマルチパートアップロードを再開できるかの判定に使う
+    pub form: EncodedForm,
+    temp: Option<PathBuf>,
+}
+
+impl StagedBlob {
+    /// 元のファイルをそのまま送る
+    fn source(upload: BlobUpload) -> Self {
+        let form = EncodedForm {
+            size: upload.size,
+            codec: Codec::None.to_string(),
+            key_id: None,
+        };
+        Self {
+            upload,
+            form,
+            temp: None,
+        }
+    }
+}
+
+impl Drop for StagedBlob {
+    fn drop(&mut self) {
+        if let Some(temp) = &self.temp {
+            let _ = fs::remove_file(temp);
+        }
+    }
+}
+
+/// 同じblobを同時にエンコードしても衝突しない一時ファイル名
+fn staging_path(staging_dir: &Path, hash: &str) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    staging_dir.join(format!("{}.{}-{}", hash, std::process::id(), n))
+}
+
+fn encode(blob: &BlobUpload, codec: Codec, keyring: Option<&Keyring>, dest: &Path) -> Result<u64> {
+    let mut src = File::open(&blob.path)?;
+    src.seek(SeekFrom::Start(blob.offset))?;
+
+    let mut out = encode_stream(
+        &mut src.take(blob.size),
+        codec,
+        keyring,
+        File::create(dest)?,
+    )?;
+    out.flush()?;
+    Ok(out.metadata()?.len())
+}
+
+/// `current_state.json` や `history.json` をエンコードする。鍵が無ければそのまま返す
+pub fn encode_bytes(data: &[u8], keyring: Option<&Keyring>) -> Result<Vec<u8>> {
+    match keyring {
+        Some(_) => encode_stream(&mut &data[..], Codec::Zstd, keyring, Vec::new()),
+        None => Ok(data.to_vec()),
+    }
+}
+
+/// `encode_bytes` の逆。ヘッダの無い従来のデータはそのまま返す
+pub fn decode_bytes(data: &[u8], keyring: Option<&Keyring>) -> Result<Vec<u8>> {
+    let mut decoder = BlobDecoder::new(Vec::new(), keyring);
+    decoder.write(data)?;
+    decoder.finish()
+}
+
+/// ヘッダを書き、圧縮してから暗号化する
+fn encode_stream<R: Read, W: Write>(
+    src: &mut R,
+    codec: Codec,
+    keyring: Option<&Keyring>,
+    mut out: W,
+) -> Result<W> {
+    let key = keyring.map(|k| k.active());
+    let header = header(codec, key);
+    out.write_all(&header)?;
+
+    match key {
+        Some(key) => {
+            let sealer = compress(src, codec, SealWriter::new(out, key, &header))?;
+            Ok(sealer.finish()?)
+        }
+        None => compress(src, codec, out),
+    }
+}
+
+fn compress<R: Read, W: Write>(src: &mut R, codec: Codec, mut out: W) -> Result<W> {
+    match codec {
+        Codec::None => {
+            io::copy(src, &mut out)?;
+            Ok(out)
+        }
+        Codec::Zstd => {
+            let mut encoder = zstd::stream::write::Encoder::new(out, ZSTD_LEVEL)?;
+            io::copy(src, &mut encoder)?;
+            Ok(encoder.finish()?)
+        }
+    }
+}
+
+fn header(codec: Codec, key: Option<&DataKey>) -> Vec<u8> {
+    let mut header = Vec::with_capacity(HEADER_LEN + KEY_ID_LEN);
+    header.extend_from_slice(MAGIC);
+    header.push(FORMAT_VERSION);
+    header.push(codec.id());
+    match key {
+        Some(key) => {
+            header.push(CIPHER_AES_GCM);
+            header.extend_from_slice(key.id());
+        }
+        None => header.push(CIPHER_NONE),
+    }
+    header
+}
+
+/// 展開・復号の各段。最後まで書き出して最終的な出力先を返す
+trait Stage<W>: Write + Send {
+    fn finish(self: Box<Self>) -> io::Result<W>;
+}
+
+/// 最終的な出力先。書き込みのエラーは `OutputError` で包み、展開・復号の失敗と区別する
+struct Output<W>(W);
+
+impl<W: Write> Write for Output<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(output_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(output_error)
+    }
+}
+
+impl<W: Write + Send> Stage<W> for Output<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<W> {
+        self.flush()?;
+        Ok(self.0)
+    }
+}
+
+/// 出力先への書き込みで起きたエラー
+#[derive(Debug)]
+struct OutputError(io::Error);
+
+impl fmt::Display for OutputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl std::error::Error for OutputError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.0)
+    }
+}
+
+fn output_error(e: io::Error) -> io::Error {
+    io::Error::new(e.kind(), OutputError(e))
+}
+
+fn is_output_error(e: &io::Error) -> bool {
+    e.get_ref().is_some_and(|inner| inner.is::<OutputError>())
+}
+
+/// zstdの展開段。壊れた圧縮データのエラーは `InvalidData` として返す
+struct Decompress<W: 'static>(zstd::stream::write::Decoder<'static, Box<dyn Stage<W>>>);
+
+impl<W> Write for Decompress<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf).map_err(decompress_error)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush().map_err(decompress_error)
+    }
+}
+
+impl<W> Stage<W> for Decompress<W> {
+    fn finish(mut self: Box<Self>) -> io::Result<W> {
+        self.flush()?;
+        self.0.into_inner().finish()
+    }
+}
+
+fn decompress_error(e: io::Error) -> io::Error {
+    if is_output_error(&e) || e.kind() == io::ErrorKind::UnexpectedEof {
+        e
+    } else {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+impl<W: 'static> Stage<W> for OpenWriter<Box<dyn Stage<W>>> {
+    fn finish(self: Box<Self>) -> io::Result<W> {
+        OpenWriter::finish(*self)?.finish()
+    }
+}
+
+/// ヘッダを見て、復号・展開しながら `W` に書き出す
+struct BlobDecoder<W> {
+    keyring: Option<Keyring>,
+    pending: Vec<u8>,
+    output: Option<W>,
+    stage: Option<Box<dyn Stage<W>>>,
+}
+
+impl<W: Write + Send + 'static> BlobDecoder<W> {
+    fn new(output: W, keyring: Option<&Keyring>) -> Self {
+        Self {
+            keyring: keyring.cloned(),
+            pending: Vec::with_capacity(HEADER_LEN + KEY_ID_LEN),
+            output: Some(output),
+            stage: None,
+        }
+    }
+
+    /// ヘッダの長さ。暗号化されている場合は鍵IDの分だけ長い
+    fn header_len(&self) -> usize {
+        let encrypted = self.pending.len() >= HEADER_LEN
+            && self.pending.starts_with(MAGIC)
+            && self.pending[HEADER_LEN - 1] != CIPHER_NONE;
+        if encrypted {
+            HEADER_LEN + KEY_ID_LEN
+        } else {
+            HEADER_LEN
+        }
+    }
+
+    fn write(&mut self, mut data: &[u8]) -> Result<()> {
+        while self.stage.is_none() {
+            let needed = self.header_len() - self.pending.len();
+            if needed == 0 {
+                self.start()?;
+                break;
+            }
+            if data.is_empty() {
+                return Ok(());
+            }
+            let take = needed.min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+
+        if let Some(stage) = self.stage.as_mut() {
+            stage.write_all(data)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<W> {
+        if self.stage.is_none() {
+            self.start()?;
+        }
+        let stage = self.stage.take().expect("出力はヘッダ判定後に必ず存在する");
+        Ok(stage.finish()?)
+    }
+
+    /// 先頭バイトからエンコード方式を判定して各段を組み立てる
+    fn start(&mut self) -> Result<()> {
+        let output = self
+            .output
+            .take()
+            .expect("出力先はヘッダ判定前にのみ保持される");
+        let mut stage: Box<dyn Stage<W>> = Box::new(Output(output));
+        let header = std::mem::take(&mut self.pending);
+
+        if header.len() < HEADER_LEN || !header.starts_with(MAGIC) {
+            stage.write_all(&header)?;
+            self.stage = Some(stage);
+            return Ok(());
+        }
+
+        if header[MAGIC.len()] != FORMAT_VERSION {
+            return Err(GpError::StorageError(format!(
+                "未対応のblob形式: v{}",
+                header[MAGIC.len()]
+            )));
+        }
+
+        if Codec::from_id(header[MAGIC.len() + 1])? == Codec::Zstd {
+            stage = Box::new(Decompress(zstd::stream::write::Decoder::new(stage)?));
+        }
+
+        match header[HEADER_LEN - 1] {
+            CIPHER_NONE => {}
+            CIPHER_AES_GCM => {
+                let keyring = self.keyring.as_ref().ok_or_else(|| {
+                    GpError::KeyError(
+                        "暗号化されたデータです。鍵ファイルを設定してください".to_string(),
+                    )
+                })?;
+                let key = keyring.get(&header[HEADER_LEN..])?;
+                stage = Box::new(OpenWriter::new(stage, key, &header));
+            }
+            cipher => {
+                return Err(GpError::StorageError(format!(
+                    "未対応の暗号方式: {}",
+                    cipher
+                )))
+            }
+        }
+
+        self.stage = Some(stage);
+        Ok(())
+    }
+}
+
+/// 展開後のデータをファイルに書き込みつつSHA256を計算し、プログレスバーに反映する
+struct BlobWriter {
+    file: Option<File>,
+    hasher: Sha256,
+    pb: ProgressBar,
+    written: u64,
+}
+
+impl Write for BlobWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.file.as_mut() {
+            Some(file) => file.write(buf)?,
+            None => buf.len(),
+        };
+        self.hasher.update(&buf[..n]);
+        self.written += n as u64;
+        self.pb.inc(n as u64);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// 復号・展開を担うブロッキング用スレッドへ、受信済みのまま渡せるチャンク数
+const SINK_QUEUE_LEN: usize = 4;
+
+/// ダウンロード中のblobを受け取り、ヘッダを見て透過的に復号・展開しながら書き込む
+///
+/// 復号・展開とファイルへの書き込みは `spawn_blocking` のスレッドで行い、
+/// 受信側は受け取ったバイト列を順に渡すだけにする。
+/// 展開後の内容はblob名のSHA256と照合し、一致しなければ `GpError::HashMismatch` を返す。
+pub struct BlobSink {
+    hash: String,
+    chunks: Option<mpsc::Sender<Vec<u8>>>,
+    worker: Option<JoinHandle<Result<BlobWriter>>>,
+}
+
+impl BlobSink {
+    /// `target.path` を開き、`target.offset` の位置から書き込む
+    pub fn open(target: &DownloadTarget, keyring: Option<&Keyring>, pb: &ProgressBar) -> Self {
+        let target = target.clone();
+        Self::spawn(&target.hash, keyring, pb, move || {
+            let mut file = OpenOptions::new().write(true).open(&target.path)?;
+            file.seek(SeekFrom::Start(target.offset))?;
+            Ok(Some(file))
+        })
+    }
+
+    /// 書き込み先を持たず、ハッシュの検証だけを行う
+    pub fn verify_only(hash: &str, keyring: Option<&Keyring>, pb: &ProgressBar) -> Self {
+        Self::spawn(hash, keyring, pb, || Ok(None))
+    }
+
+    fn spawn(
+        hash: &str,
+        keyring: Option<&Keyring>,
+        pb: &ProgressBar,
+        open: impl FnOnce() -> Result<Option<File>> + Send + 'static,
+    ) -> Self {
+        let (tx, mut rx) = mpsc::channel::<Vec<u8>>(SINK_QUEUE_LEN);
+        let keyring = keyring.cloned();
+        let pb = pb.clone();
+
+        let worker = tokio::task::spawn_blocking(move || {
+            let writer = BlobWriter {
+                file: open()?,
+                hasher: Sha256::new(),
+                pb,
+                written: 0,
+            };
+            let mut decoder = BlobDecoder::new(writer, keyring.as_ref());
+            while let Some(chunk) = rx.blocking_recv() {
+                decoder.write(&chunk)?;
+            }
+            decoder.finish()
+        });
+
+        Self {
+            hash: hash.to_string(),
+            chunks: Some(tx),
+            worker: Some(worker),
+        }
+    }
+
+    pub async fn write(&mut self, data: &[u8]) -> Result<()> {
+        let Some(chunks) = &self.chunks else {
+            return Ok(());
+        };
+        if chunks.send(data.to_vec()).await.is_err() {
+            // 復号・展開側が先に失敗して受け取りをやめた
+            self.chunks = None;
+            self.join().await?;
+        }
+        Ok(())
+    }
+
+    /// 書き込みを完了してハッシュを照合し、展開後のバイト数を返す
+    pub async fn finish(mut self) -> Result<u64> {
+        self.chunks = None;
+        let writer = self.join().await?;
+        let actual = format!("{:x}", writer.hasher.finalize());
+        if actual != self.hash {
+            return Err(GpError::HashMismatch {
+                expected: self.hash,
+                actual,
+            });
+        }
+        Ok(writer.written)
+    }
+
+    async fn join(&mut self) -> Result<BlobWriter> {
+        let worker = self.worker.take().ok_or_else(|| {
+            GpError::StorageError(format!("{}: 書き込みは終了しています", self.hash))
+        })?;
+        worker
+            .await
+            .map_err(|e| GpError::StorageError(e.to_string()))?
+            .map_err(|e| corrupted(&self.hash, e))
+    }
+}
+
+/// 復号・展開の失敗（認証タグの不一致や壊れた圧縮データ）はblobの破損として扱う
+///
+/// 書き込み先のファイルで起きたエラーは破損ではないため、元のIOエラーに戻して返す。
+fn corrupted(hash: &str, error: GpError) -> GpError {
+    match error {
+        GpError::IoError(e) if is_output_error(&e) => {
+            let kind = e.kind();
+            let e = e
+                .into_inner()
+                .and_then(|inner| inner.downcast::<OutputError>().ok())
+                .map(|output| output.0)
+                .unwrap_or_else(|| kind.into());
+            GpError::IoError(e)
+        }
+        GpError::IoError(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+            ) =>
+        {
+            GpError::CorruptBlob(format!("{}: {}", hash, e))
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    /// `blob` を `feed` バイトずつ流し込んで復元し、`expected` と一致することを確認する
+    async fn restore(
+        blob: &[u8],
+        expected: &[u8],
+        keyring: Option<&Keyring>,
+        dir: &Path,
+        feed: usize,
+    ) {
+        let path = dir.join("restored");
+        File::create(&path).unwrap();
+        let target = DownloadTarget {
+            hash: sha256(expected),
+            path: path.clone(),
+            offset: 0,
+            size: 0,
+        };
+
+        let mut sink = BlobSink::open(&target, keyring, &ProgressBar::hidden());
+        for chunk in blob.chunks(feed) {
+            sink.write(chunk).await.unwrap();
+        }
+        assert_eq!(sink.finish().await.unwrap(), expected.len() as u64);
+        assert_eq!(fs::read(&path).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_codec_selection_by_file_type() {
+        assert_eq!(Codec::Zstd.for_path(Path::new("Song.als")), Codec::Zstd);
+        assert_eq!(Codec::Zstd.for_path(Path::new("mix.RPP")), Codec::Zstd);
+        assert_eq!(Codec::Zstd.for_path(Path::new("beat.mid")), Codec::Zstd);
+        assert_eq!(Codec::Zstd.for_path(Path::new("kick.wav")), Codec::None);
+        assert_eq!(Codec::Zstd.for_path(Path::new("ref.mp3")), Codec::None);
+        assert_eq!(Codec::None.for_path(Path::new("Song.als")), Codec::None);
+    }
+
+    #[tokio::test]
+    async fn test_stage_and_restore_compressed_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("Song.rpp");
+        let content = "<REAPER_PROJECT 0.1\n  <TRACK\n  >\n>\n".repeat(200);
+        fs::write(&source, &content).unwrap();
+
+        let blob = BlobUpload {
+            hash: "h".to_string(),
+            path: source.clone(),
+            offset: 0,
+            size: content.len() as u64,
+        };
+        let staged = BlobEncoding::new(Codec::Zstd, None, &dir.path().join("staging"))
+            .stage(&blob)
+            .await
+            .unwrap();
+
+        assert_ne!(staged.upload.path, source);
+        assert!(staged.upload.size < content.len() as u64);
+
+        let encoded = fs::read(&staged.upload.path).unwrap();
+        assert!(encoded.starts_with(MAGIC));
+        for feed in [1, 7, 4096] {
+            restore(&encoded, content.as_bytes(), None, dir.path(), feed).await;
+        }
+
+        let temp = staged.upload.path.clone();
+        drop(staged);
+        assert!(!temp.exists());
+    }
+
+    #[tokio::test]
+    async fn test_incompressible_blob_stays_raw() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("tiny.als");
+        fs::write(&source, b"x").unwrap();
+
+        let blob = BlobUpload {
+            hash: "h".to_string(),
+            path: source.clone(),
+            offset: 0,
+            size: 1,
+        };
+        let staged = BlobEncoding::new(Codec::Zstd, None, &dir.path().join("staging"))
+            .stage(&blob)
+            .await
+            .unwrap();
+        assert_eq!(staged.upload.path, source);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_raw_blob_passes_through() {
+        let dir = tempfile::tempdir().unwrap();
+        restore(b"take 1", b"take 1", None, dir.path(), 2).await;
+        restore(b"", b"", None, dir.path(), 1).await;
+
+        let long: Vec<u8> = (0..100u8).collect();
+        restore(&long, &long, None, dir.path(), 3).await;
+    }
+
+    #[tokio::test]
+    async fn test_encrypted_blob_needs_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("master.wav");
+        let content = vec![42u8; 3000];
+        fs::write(&source, &content).unwrap();
+
+        let keyring = Keyring::generate();
+        let blob = BlobUpload {
+            hash: "h".to_string(),
+            path: source.clone(),
+            offset: 0,
+            size: content.len() as u64,
+        };
+        let staged = BlobEncoding::new(Codec::Zstd, Some(&keyring), &dir.path().join("staging"))
+            .stage(&blob)
+            .await
+            .unwrap();
+        assert_ne!(staged.upload.path, source);
+
+        let encoded = fs::read(&staged.upload.path).unwrap();
+        assert!(!encoded.windows(content.len()).any(|w| w == content));
+        restore(&encoded, &content, Some(&keyring), dir.path(), 5).await;
+
+        let target = DownloadTarget {
+            hash: "h".to_string(),
+            path: dir.path().join("restored"),
+            offset: 0,
+            size: 0,
+        };
+        let mut sink = BlobSink::open(&target, None, &ProgressBar::hidden());
+        let result = match sink.write(&encoded).await {
+            Ok(()) => sink.finish().await,
+            Err(e) => Err(e),
+        };
+        assert!(matches!(result, Err(GpError::KeyError(_))));
+    }
+
+    #[tokio::test]
+    async fn test_broken_compressed_blob_is_corrupt() {
+        let mut blob = header(Codec::Zstd, None);
+        blob.extend_from_slice(b"not a zstd frame at all");
+
+        let mut sink = BlobSink::verify_only(&sha256(b"take 1"), None, &ProgressBar::hidden());
+        let result = match sink.write(&blob).await {
+            Ok(()) => sink.finish().await,
+            Err(e) => Err(e),
+        };
+        assert!(matches!(result, Err(GpError::CorruptBlob(_))));
+    }
+
+    #[tokio::test]
+    async fn test_sink_rejects_hash_mismatch() {
+        let mut sink = BlobSink::verify_only(&sha256(b"take 1"), None, &ProgressBar::hidden());
+        sink.write(b"take 2").await.unwrap();
+        assert!(matches!(
+            sink.finish().await,
+            Err(GpError::HashMismatch { actual, .. }) if actual == sha256(b"take 2")
+        ));
+    }
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let keyring = Keyring::generate();
+        let json = br#"{"Song.als":"abc"}"#;
+
+        assert_eq!(encode_bytes(json, None).unwrap(), json);
+        assert_eq!(decode_bytes(json, Some(&keyring)).unwrap(), json);
+
+        let encoded = encode_bytes(json, Some(&keyring)).unwrap();
+        assert!(encoded.starts_with(MAGIC));
+        assert_eq!(decode_bytes(&encoded, Some(&keyring)).unwrap(), json);
+        assert!(decode_bytes(&encoded, Some(&Keyring::generate())).is_err());
+    }
+}
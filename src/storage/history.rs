@@ -1,6 +1,9 @@
+use crate::error::{GpError, Result};
+use crate::scanner::FileEntry;
 use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobSetBuilder};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SnapshotMeta {
@@ -9,34 +12,74 @@ pub struct SnapshotMeta {
     pub changed_count: usize,
 }
 
+/// 大きなファイルを構成するチャンク（blobとして個別に保存される）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkRef {
+    pub hash: String,
+    pub size: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Snapshot {
     pub id: String,
     pub created_at: DateTime<Utc>,
     pub message: Option<String>,
     pub files: HashMap<String, String>,
+    /// ファイルパス → サイズ（古い履歴には存在しない）
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sizes: HashMap<String, u64>,
+    /// ファイルハッシュ → チャンク一覧。ここに無いファイルは丸ごと1つのblobとして保存されている
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub chunks: HashMap<String, Vec<ChunkRef>>,
     pub parent_id: Option<String>,
     pub meta: SnapshotMeta,
 }
 
+/// 最初からあるブランチ。先頭は `History::head` に保存する
+pub const DEFAULT_BRANCH: &str = "main";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct History {
     pub version: u32,
     pub project_name: String,
+    /// `main` ブランチの先頭
     pub head: Option<String>,
     pub snapshots: Vec<Snapshot>,
+    /// `main` 以外のブランチ名 → 先頭のスナップショットID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub branches: BTreeMap<String, String>,
+    /// タグ名 → スナップショットID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
 }
 
+/// スナップショットIDの形（`generate_id` の `%Y%m%dT%H%M%S%.3fZ`）。`0` の位置は数字
+const ID_SHAPE: &str = "00000000T000000.000Z";
+
 impl Snapshot {
     pub fn generate_id() -> String {
         Utc::now().format("%Y%m%dT%H%M%S%.3fZ").to_string()
     }
 
+    /// スナップショットIDまたはその先頭部分として読める名前か（`2026`、`20260203T14` など）
+    pub fn looks_like_id(name: &str) -> bool {
+        !name.is_empty()
+            && name.len() <= ID_SHAPE.len()
+            && name.chars().zip(ID_SHAPE.chars()).all(|(c, shape)| {
+                if shape == '0' {
+                    c.is_ascii_digit()
+                } else {
+                    c == shape
+                }
+            })
+    }
+
     pub fn new(
         message: Option<String>,
         files: HashMap<String, String>,
+        sizes: HashMap<String, u64>,
+        chunks: HashMap<String, Vec<ChunkRef>>,
         parent_id: Option<String>,
-        total_size: u64,
         changed_count: usize,
     ) -> Self {
         Self {
@@ -45,13 +88,78 @@ impl Snapshot {
             message,
             meta: SnapshotMeta {
                 file_count: files.len(),
-                total_size,
+                total_size: sizes.values().sum(),
                 changed_count,
             },
             files,
+            sizes,
+            chunks,
             parent_id,
         }
     }
+
+    /// 比較用のファイル一覧（パス → 内容）。サイズが記録されていないファイルは0
+    pub fn file_entries(&self) -> BTreeMap<String, FileEntry> {
+        self.files
+            .iter()
+            .map(|(path, hash)| {
+                let entry = FileEntry {
+                    hash: hash.clone(),
+                    size: self.sizes.get(path).copied().unwrap_or(0),
+                };
+                (path.clone(), entry)
+            })
+            .collect()
+    }
+
+    /// パスまたはglobパターンに一致するファイル
+    ///
+    /// フォルダを指定した場合はその中のファイルすべてに一致する。
+    /// `*` はフォルダの区切りをまたがないため、下の階層も含めるには `**` を使う。
+    pub fn matching_files(&self, patterns: &[String]) -> Result<BTreeSet<String>> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let glob = GlobBuilder::new(pattern.trim_end_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| GpError::InvalidPattern(format!("{}: {}", pattern, e)))?;
+            builder.add(glob);
+        }
+        let globs = builder
+            .build()
+            .map_err(|e| GpError::InvalidPattern(e.to_string()))?;
+
+        // ファイル自身か、その親フォルダのいずれかが一致すればよい
+        Ok(self
+            .files
+            .keys()
+            .filter(|path| {
+                std::path::Path::new(path.as_str())
+                    .ancestors()
+                    .any(|p| !p.as_os_str().is_empty() && globs.is_match(p))
+            })
+            .cloned()
+            .collect())
+    }
+
+    /// このスナップショットが参照するblob（ハッシュ → サイズ）。サイズが不明な場合は0
+    pub fn blobs(&self) -> BTreeMap<String, u64> {
+        let mut blobs = BTreeMap::new();
+        for (path, hash) in &self.files {
+            match self.chunks.get(hash) {
+                Some(chunks) => {
+                    for chunk in chunks {
+                        blobs.insert(chunk.hash.clone(), chunk.size);
+                    }
+                }
+                None => {
+                    let size = self.sizes.get(path).copied().unwrap_or(0);
+                    blobs.insert(hash.clone(), size);
+                }
+            }
+        }
+        blobs
+    }
 }
 
 impl History {
@@ -61,20 +169,171 @@ impl History {
             project_name: project_name.to_string(),
             head: None,
             snapshots: Vec::new(),
+            branches: BTreeMap::new(),
+            tags: BTreeMap::new(),
         }
     }
 
-    pub fn add_snapshot(&mut self, snapshot: Snapshot) {
-        self.head = Some(snapshot.id.clone());
+    /// スナップショットを追加し、`branch` の先頭だけを進める
+    pub fn add_snapshot_to(&mut self, branch: &str, snapshot: Snapshot) {
+        self.set_branch_head(branch, &snapshot.id);
         self.snapshots.push(snapshot);
     }
 
+    /// ブランチの先頭のスナップショットID
+    pub fn branch_head_id(&self, branch: &str) -> Option<&str> {
+        if branch == DEFAULT_BRANCH {
+            self.head.as_deref()
+        } else {
+            self.branches.get(branch).map(String::as_str)
+        }
+    }
+
+    /// ブランチの先頭のスナップショット
+    pub fn branch_head(&self, branch: &str) -> Option<&Snapshot> {
+        let id = self.branch_head_id(branch)?;
+        self.snapshots.iter().rev().find(|s| s.id == id)
+    }
+
+    /// ブランチの先頭を設定する。無ければブランチを作る
+    pub fn set_branch_head(&mut self, branch: &str, snapshot_id: &str) {
+        if branch == DEFAULT_BRANCH {
+            self.head = Some(snapshot_id.to_string());
+        } else {
+            self.branches
+                .insert(branch.to_string(), snapshot_id.to_string());
+        }
+    }
+
+    pub fn has_branch(&self, branch: &str) -> bool {
+        branch == DEFAULT_BRANCH || self.branches.contains_key(branch)
+    }
+
+    /// すべてのブランチ名（`main` が先頭）
+    pub fn branch_names(&self) -> Vec<&str> {
+        std::iter::once(DEFAULT_BRANCH)
+            .chain(self.branches.keys().map(String::as_str))
+            .collect()
+    }
+
+    /// 先頭がこのスナップショットのブランチ名
+    pub fn branches_of(&self, snapshot_id: &str) -> Vec<&str> {
+        self.branch_names()
+            .into_iter()
+            .filter(|branch| self.branch_head_id(branch) == Some(snapshot_id))
+            .collect()
+    }
+
+    /// スナップショットから `parent_id` をたどった祖先（自身を含む、新しい順）
+    pub fn ancestors(&self, snapshot_id: &str) -> Vec<&Snapshot> {
+        let by_id: HashMap<&str, &Snapshot> =
+            self.snapshots.iter().map(|s| (s.id.as_str(), s)).collect();
+
+        let mut chain = Vec::new();
+        let mut next = by_id.get(snapshot_id).copied();
+        while let Some(snapshot) = next {
+            chain.push(snapshot);
+            next = snapshot
+                .parent_id
+                .as_deref()
+                .and_then(|id| by_id.get(id).copied());
+        }
+        chain
+    }
+
+    pub fn head_snapshot(&self) -> Option<&Snapshot> {
+        let head = self.head.as_deref()?;
+        self.snapshots.iter().rev().find(|s| s.id == head)
+    }
+
     pub fn find_snapshot_by_prefix(&self, prefix: &str) -> Option<&Snapshot> {
         self.snapshots
             .iter()
             .rev()
             .find(|s| s.id.starts_with(prefix))
     }
+
+    /// 完全なID・タグ名・ブランチ名（先頭）・IDの先頭部分の順にスナップショットを探す
+    pub fn find_snapshot(&self, name_or_prefix: &str) -> Option<&Snapshot> {
+        if let Some(snapshot) = self.snapshots.iter().find(|s| s.id == name_or_prefix) {
+            return Some(snapshot);
+        }
+        if let Some(id) = self.tags.get(name_or_prefix) {
+            return self.snapshots.iter().find(|s| &s.id == id);
+        }
+        if self.has_branch(name_or_prefix) {
+            return self.branch_head(name_or_prefix);
+        }
+        self.find_snapshot_by_prefix(name_or_prefix)
+    }
+
+    /// スナップショットに付いているタグ名
+    pub fn tags_of(&self, snapshot_id: &str) -> Vec<&str> {
+        self.tags
+            .iter()
+            .filter(|(_, id)| id.as_str() == snapshot_id)
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    /// スナップショットを履歴から取り除く
+    ///
+    /// 取り除いたスナップショットを親に持つスナップショットは、その先で最も近い
+    /// 残っている祖先を親に付け替えるため、`parent_id` の連鎖は途切れない。
+    /// 先頭を取り除かれたブランチも同様に祖先まで戻す。
+    pub fn remove_snapshots(&mut self, ids: &HashSet<String>) {
+        let parents: HashMap<String, Option<String>> = self
+            .snapshots
+            .iter()
+            .map(|s| (s.id.clone(), s.parent_id.clone()))
+            .collect();
+
+        self.snapshots.retain(|s| !ids.contains(&s.id));
+
+        // 取り除かれていない最も近い祖先（自身が残っていれば自身）
+        let kept_ancestor = |id: &String| {
+            let mut current = Some(id.clone());
+            while let Some(id) = current.as_ref().filter(|id| ids.contains(*id)) {
+                current = parents.get(id).cloned().flatten();
+            }
+            current
+        };
+
+        for snapshot in &mut self.snapshots {
+            snapshot.parent_id = snapshot.parent_id.as_ref().and_then(kept_ancestor);
+        }
+
+        self.tags.retain(|_, id| !ids.contains(id));
+
+        // ブランチの先頭が取り除かれたら、残っている祖先まで戻す
+        self.branches = std::mem::take(&mut self.branches)
+            .into_iter()
+            .filter_map(|(branch, id)| kept_ancestor(&id).map(|id| (branch, id)))
+            .collect();
+        // main の祖先がすべて取り除かれたら、他のブランチのスナップショットには付け替えない
+        self.head = self.head.as_ref().and_then(kept_ancestor);
+    }
+
+    /// いずれかのスナップショットから参照されているblob（ハッシュ → サイズ）
+    pub fn referenced_blobs(&self) -> BTreeMap<String, u64> {
+        let mut blobs = BTreeMap::new();
+        for snapshot in &self.snapshots {
+            for (hash, size) in snapshot.blobs() {
+                let known = blobs.entry(hash).or_insert(0);
+                *known = (*known).max(size);
+            }
+        }
+        blobs
+    }
+
+    /// 全スナップショットのチャンク一覧（ファイルハッシュ → チャンク一覧）
+    pub fn chunk_manifests(&self) -> HashMap<String, Vec<ChunkRef>> {
+        self.snapshots
+            .iter()
+            .flat_map(|s| s.chunks.iter())
+            .map(|(hash, chunks)| (hash.clone(), chunks.clone()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
@@ -108,6 +367,8 @@ mod tests {
             created_at: Utc::now(),
             message: Some("test".to_string()),
             files,
+            sizes: HashMap::new(),
+            chunks: HashMap::new(),
             parent_id: None,
             meta: SnapshotMeta {
                 file_count: 1,
@@ -115,10 +376,213 @@ mod tests {
                 changed_count: 1,
             },
         };
-        history.add_snapshot(snapshot);
+        history.add_snapshot_to(DEFAULT_BRANCH, snapshot);
 
         assert!(history.find_snapshot_by_prefix("20260203").is_some());
         assert!(history.find_snapshot_by_prefix("202602").is_some());
         assert!(history.find_snapshot_by_prefix("20250101").is_none());
     }
+
+    #[test]
+    fn test_id_like_names() {
+        assert!(Snapshot::looks_like_id("2026"));
+        assert!(Snapshot::looks_like_id("20260203T14"));
+        assert!(Snapshot::looks_like_id(&Snapshot::generate_id()));
+        assert!(!Snapshot::looks_like_id(""));
+        assert!(!Snapshot::looks_like_id("2nd-mix"));
+        assert!(!Snapshot::looks_like_id("20260203-final"));
+        assert!(!Snapshot::looks_like_id("20260203T143052.000Z1"));
+    }
+
+    #[test]
+    fn test_find_snapshot_by_tag() {
+        let mut history = History::new("test");
+        for id in ["20260203T143052.000Z", "20260204T090000.000Z"] {
+            let mut snapshot = Snapshot::new(
+                None,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                None,
+                0,
+            );
+            snapshot.id = id.to_string();
+            history.add_snapshot_to(DEFAULT_BRANCH, snapshot);
+        }
+        history
+            .tags
+            .insert("mix-v3".to_string(), "20260203T143052.000Z".to_string());
+
+        // 以前のバージョンで作られた、IDと紛らわしいタグより完全なIDを優先する
+        history.tags.insert(
+            "20260204T090000.000Z".to_string(),
+            "20260203T143052.000Z".to_string(),
+        );
+
+        let find = |name: &str| history.find_snapshot(name).map(|s| s.id.as_str());
+        assert_eq!(find("mix-v3"), Some("20260203T143052.000Z"));
+        assert_eq!(find("20260204"), Some("20260204T090000.000Z"));
+        assert_eq!(find("20260204T090000.000Z"), Some("20260204T090000.000Z"));
+        assert_eq!(find("mix"), None);
+        history.tags.remove("20260204T090000.000Z");
+        assert_eq!(history.tags_of("20260203T143052.000Z"), vec!["mix-v3"]);
+
+        let json = serde_json::to_string(&history).unwrap();
+        let restored: History = serde_json::from_str(&json).unwrap();
+        assert_eq!(restored.tags, history.tags);
+
+        history.remove_snapshots(&HashSet::from(["20260203T143052.000Z".to_string()]));
+        assert!(history.tags.is_empty());
+    }
+
+    #[test]
+    fn test_remove_snapshots_relinks_parents() {
+        let mut history = History::new("test");
+        for (id, parent) in [
+            ("a", None),
+            ("b", Some("a")),
+            ("c", Some("b")),
+            ("d", Some("c")),
+        ] {
+            let mut snapshot = Snapshot::new(
+                None,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                parent.map(String::from),
+                0,
+            );
+            snapshot.id = id.to_string();
+            history.add_snapshot_to(DEFAULT_BRANCH, snapshot);
+        }
+
+        let removed: HashSet<String> = ["b", "c"].iter().map(|s| s.to_string()).collect();
+        history.remove_snapshots(&removed);
+
+        let ids: Vec<&str> = history.snapshots.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, vec!["a", "d"]);
+        assert_eq!(history.snapshots[1].parent_id.as_deref(), Some("a"));
+        assert_eq!(history.head.as_deref(), Some("d"));
+    }
+
+    #[test]
+    fn test_branches_advance_independently() {
+        let mut history = History::new("test");
+        let add = |history: &mut History, branch: &str, id: &str| {
+            let parent = history.branch_head_id(branch).map(String::from);
+            let mut snapshot = Snapshot::new(
+                None,
+                HashMap::new(),
+                HashMap::new(),
+                HashMap::new(),
+                parent,
+                0,
+            );
+            snapshot.id = id.to_string();
+            history.add_snapshot_to(branch, snapshot);
+        };
+
+        add(&mut history, DEFAULT_BRANCH, "a");
+        add(&mut history, DEFAULT_BRANCH, "b");
+        history.set_branch_head("remix", "a");
+        add(&mut history, "remix", "r1");
+        add(&mut history, "remix", "r2");
+        add(&mut history, DEFAULT_BRANCH, "c");
+
+        assert_eq!(history.head.as_deref(), Some("c"));
+        assert_eq!(history.branch_head_id("remix"), Some("r2"));
+        assert_eq!(history.branch_names(), vec!["main", "remix"]);
+        assert_eq!(history.branches_of("r2"), vec!["remix"]);
+        assert_eq!(history.find_snapshot("remix").unwrap().id, "r2");
+
+        let chain = |history: &History, id: &str| -> Vec<String> {
+            history.ancestors(id).iter().map(|s| s.id.clone()).collect()
+        };
+        assert_eq!(chain(&history, "r2"), ["r2", "r1", "a"]);
+        assert_eq!(chain(&history, "c"), ["c", "b", "a"]);
+
+        history.remove_snapshots(&HashSet::from(["r2".to_string(), "b".to_string()]));
+        assert_eq!(history.branch_head_id("remix"), Some("r1"));
+        assert_eq!(chain(&history, "c"), ["c", "a"]);
+
+        // main のスナップショットがすべて無くなっても、remix の先頭を main にしない
+        history.remove_snapshots(&HashSet::from(["a".to_string(), "c".to_string()]));
+        assert_eq!(history.head, None);
+        assert_eq!(history.branch_head_id("remix"), Some("r1"));
+        assert_eq!(chain(&history, "r1"), ["r1"]);
+    }
+
+    #[test]
+    fn test_matching_files() {
+        let files = [
+            "Song.als",
+            "Old/Song.als",
+            "Samples/kick.wav",
+            "Samples/Drums/snare.wav",
+            "Presets/lead.fxp",
+        ]
+        .iter()
+        .map(|p| (p.to_string(), "abc123".to_string()))
+        .collect();
+        let snapshot = Snapshot::new(None, files, HashMap::new(), HashMap::new(), None, 0);
+        let matching = |patterns: &[&str]| {
+            let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+            snapshot
+                .matching_files(&patterns)
+                .unwrap()
+                .into_iter()
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(matching(&["Song.als"]), ["Song.als"]);
+        assert_eq!(matching(&["*.als"]), ["Song.als"]);
+        assert_eq!(matching(&["**/*.als"]), ["Old/Song.als", "Song.als"]);
+        assert_eq!(
+            matching(&["Samples/", "Presets/*.fxp"]),
+            [
+                "Presets/lead.fxp",
+                "Samples/Drums/snare.wav",
+                "Samples/kick.wav"
+            ]
+        );
+        assert!(matching(&["missing.als"]).is_empty());
+        assert!(snapshot.matching_files(&["[".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_referenced_blobs_resolve_chunks() {
+        let mut files = HashMap::new();
+        files.insert("a.als".to_string(), "aaa".to_string());
+        files.insert("mix.wav".to_string(), "mix".to_string());
+        let mut sizes = HashMap::new();
+        sizes.insert("a.als".to_string(), 10);
+        sizes.insert("mix.wav".to_string(), 30);
+        let mut chunks = HashMap::new();
+        chunks.insert(
+            "mix".to_string(),
+            vec![
+                ChunkRef {
+                    hash: "c1".to_string(),
+                    size: 20,
+                },
+                ChunkRef {
+                    hash: "c2".to_string(),
+                    size: 10,
+                },
+            ],
+        );
+
+        let mut history = History::new("test");
+        history.add_snapshot_to(
+            DEFAULT_BRANCH,
+            Snapshot::new(None, files, sizes, chunks, None, 2),
+        );
+
+        let blobs = history.referenced_blobs();
+        assert_eq!(blobs.len(), 3);
+        assert_eq!(blobs["aaa"], 10);
+        assert_eq!(blobs["c1"], 20);
+        assert!(!blobs.contains_key("mix"));
+        assert!(history.chunk_manifests().contains_key("mix"));
+    }
 }
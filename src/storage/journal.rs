@@ -0,0 +1,559 @@
+use crate::error::{GpError, Result};
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const JOURNAL_FILE: &str = "push_journal.jsonl";
+
+/// 送信済みの記録を信用する時間
+///
+/// `gp gc` は猶予期間（既定24時間）を過ぎた未参照のblobを削除するため、
+/// 古い記録のblobはリモートから消えている可能性がある。
+/// これを過ぎた記録は無視し、もう一度リモートに存在を確認する。
+const COMPLETED_TTL_HOURS: i64 = 1;
+
+/// マルチパートで送るエンコード済みのblobの形式
+///
+/// 再開時に今回エンコードしたものと比べ、鍵・圧縮方式・サイズが変わっていれば
+/// 送信済みのパートを捨てる（異なる内容のパートが混ざった壊れたblobを作らない）。
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodedForm {
+    /// エンコード後のバイト数
+    pub size: u64,
+    /// 圧縮方式（`zstd` / `none`）
+    pub codec: String,
+    /// 暗号化した鍵のID。暗号化しない場合は `None`
+    pub key_id: Option<String>,
+}
+
+/// 途中まで進んだマルチパートアップロード
+#[derive(Debug, Clone, Default)]
+pub struct MultipartState {
+    pub upload_id: String,
+    pub part_size: u64,
+    pub form: EncodedForm,
+    /// パート番号 → ETag
+    pub parts: BTreeMap<i32, String>,
+}
+
+/// 記録ファイルの1行。先頭行は常に `Remote`
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Remote {
+        remote: String,
+    },
+    Completed {
+        hash: String,
+        at: DateTime<Utc>,
+    },
+    Multipart {
+        hash: String,
+        upload_id: String,
+        part_size: u64,
+        /// 形式を記録していない古い記録は、どのblobとも一致しないものとして扱う
+        #[serde(default)]
+        form: EncodedForm,
+    },
+    Part {
+        hash: String,
+        part_number: i32,
+        e_tag: String,
+    },
+    Discard {
+        hash: String,
+    },
+}
+
+#[derive(Debug, Default)]
+struct JournalData {
+    /// blobのハッシュ → 送信・確認した時刻
+    completed: BTreeMap<String, DateTime<Utc>>,
+    multipart: HashMap<String, MultipartState>,
+}
+
+impl JournalData {
+    fn apply(&mut self, entry: Entry) {
+        match entry {
+            Entry::Remote { .. } => {}
+            Entry::Completed { hash, at } => {
+                self.multipart.remove(&hash);
+                self.completed.insert(hash, at);
+            }
+            Entry::Multipart {
+                hash,
+                upload_id,
+                part_size,
+                form,
+            } => {
+                self.multipart.insert(
+                    hash,
+                    MultipartState {
+                        upload_id,
+                        part_size,
+                        form,
+                        parts: BTreeMap::new(),
+                    },
+                );
+            }
+            Entry::Part {
+                hash,
+                part_number,
+                e_tag,
+            } => {
+                if let Some(state) = self.multipart.get_mut(&hash) {
+                    state.parts.insert(part_number, e_tag);
+                }
+            }
+            Entry::Discard { hash } => {
+                self.multipart.remove(&hash);
+            }
+        }
+    }
+
+    /// 現在の状態を再現する最小の記録
+    fn entries(&self) -> Vec<Entry> {
+        let mut entries: Vec<Entry> = self
+            .completed
+            .iter()
+            .map(|(hash, at)| Entry::Completed {
+                hash: hash.clone(),
+                at: *at,
+            })
+            .collect();
+        for (hash, state) in &self.multipart {
+            entries.push(Entry::Multipart {
+                hash: hash.clone(),
+                upload_id: state.upload_id.clone(),
+                part_size: state.part_size,
+                form: state.form.clone(),
+            });
+            entries.extend(state.parts.iter().map(|(part_number, e_tag)| Entry::Part {
+                hash: hash.clone(),
+                part_number: *part_number,
+                e_tag: e_tag.clone(),
+            }));
+        }
+        entries
+    }
+}
+
+/// 中断されたプッシュを再開するためのアップロード記録（`.gp/push_journal.jsonl`）
+///
+/// リモートに存在を確認したblobと、進行中のマルチパートアップロードを記録する。
+/// 記録は1件ごとにJSON Linesとして追記し、書き込みはブロッキング用スレッドで行う。
+/// プッシュが完了したら `clear()` で削除する。
+#[derive(Clone)]
+pub struct UploadJournal {
+    file: Option<Arc<Mutex<JournalFile>>>,
+    data: Arc<Mutex<JournalData>>,
+}
+
+impl UploadJournal {
+    /// 記録を読み込む。別のリモート向けの記録だった場合は破棄して新しく始める
+    ///
+    /// 読み込んだ記録は現在の状態だけに書き直し、追記で伸びたファイルを縮める。
+    pub fn open(gp_dir: &Path, remote: &str) -> Result<Self> {
+        let path = gp_dir.join(JOURNAL_FILE);
+
+        let data = match fs::read(&path) {
+            Ok(bytes) => replay(&bytes, remote),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut file = JournalFile {
+            path,
+            remote: remote.to_string(),
+            writer: None,
+        };
+        let data = match data {
+            Some(data) => {
+                file.rewrite(&data.entries())?;
+                data
+            }
+            None => {
+                file.remove()?;
+                JournalData::default()
+            }
+        };
+
+        Ok(Self {
+            file: Some(Arc::new(Mutex::new(file))),
+            data: Arc::new(Mutex::new(data)),
+        })
+    }
+
+    /// ディスクに保存しない記録（再開の必要がない一度きりの転送用）
+    pub fn in_memory() -> Self {
+        Self {
+            file: None,
+            data: Arc::new(Mutex::new(JournalData::default())),
+        }
+    }
+
+    /// `COMPLETED_TTL_HOURS` 以内に送信・確認したblobか
+    pub fn is_completed(&self, hash: &str) -> bool {
+        let cutoff = completed_cutoff();
+        lock(&self.data)
+            .completed
+            .get(hash)
+            .is_some_and(|at| *at > cutoff)
+    }
+
+    pub fn completed_count(&self) -> usize {
+        let cutoff = completed_cutoff();
+        lock(&self.data)
+            .completed
+            .values()
+            .filter(|at| **at > cutoff)
+            .count()
+    }
+
+    pub async fn mark_completed(&self, hash: &str) -> Result<()> {
+        self.mark_completed_at(hash, Utc::now()).await
+    }
+
+    async fn mark_completed_at(&self, hash: &str, at: DateTime<Utc>) -> Result<()> {
+        self.record(Entry::Completed {
+            hash: hash.to_string(),
+            at,
+        })
+        .await
+    }
+
+    pub fn multipart(&self, hash: &str) -> Option<MultipartState> {
+        lock(&self.data).multipart.get(hash).cloned()
+    }
+
+    pub async fn start_multipart(
+        &self,
+        hash: &str,
+        upload_id: &str,
+        part_size: u64,
+        form: &EncodedForm,
+    ) -> Result<()> {
+        self.record(Entry::Multipart {
+            hash: hash.to_string(),
+            upload_id: upload_id.to_string(),
+            part_size,
+            form: form.clone(),
+        })
+        .await
+    }
+
+    pub async fn record_part(&self, hash: &str, part_number: i32, e_tag: &str) -> Result<()> {
+        self.record(Entry::Part {
+            hash: hash.to_string(),
+            part_number,
+            e_tag: e_tag.to_string(),
+        })
+        .await
+    }
+
+    pub async fn discard_multipart(&self, hash: &str) -> Result<()> {
+        self.record(Entry::Discard {
+            hash: hash.to_string(),
+        })
+        .await
+    }
+
+    /// プッシュ完了後に記録を削除する
+    pub fn clear(&self) -> Result<()> {
+        let mut data = lock(&self.data);
+        data.completed.clear();
+        data.multipart.clear();
+
+        match &self.file {
+            Some(file) => lock(file).remove(),
+            None => Ok(()),
+        }
+    }
+
+    /// メモリ上の状態を更新し、ファイルに1行追記する
+    async fn record(&self, entry: Entry) -> Result<()> {
+        let Some(file) = &self.file else {
+            lock(&self.data).apply(entry);
+            return Ok(());
+        };
+
+        let line = encode_line(&entry)?;
+        lock(&self.data).apply(entry);
+
+        let file = file.clone();
+        tokio::task::spawn_blocking(move || lock(&file).append(&line))
+            .await
+            .map_err(|e| GpError::StorageError(e.to_string()))?
+    }
+}
+
+/// 記録ファイルと追記用のハンドル
+struct JournalFile {
+    path: PathBuf,
+    remote: String,
+    writer: Option<fs::File>,
+}
+
+impl JournalFile {
+    fn append(&mut self, line: &[u8]) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => {
+                if let Some(parent) = self.path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let mut writer = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                if writer.metadata()?.len() == 0 {
+                    writer.write_all(&encode_line(&self.header())?)?;
+                }
+                self.writer.insert(writer)
+            }
+        };
+        writer.write_all(line)?;
+        Ok(())
+    }
+
+    /// 記録ファイルを `entries` だけの内容に置き換える
+    fn rewrite(&mut self, entries: &[Entry]) -> Result<()> {
+        self.writer = None;
+
+        let mut body = encode_line(&self.header())?;
+        for entry in entries {
+            body.extend(encode_line(entry)?);
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn remove(&mut self) -> Result<()> {
+        self.writer = None;
+
+        match fs::remove_file(&self.path) {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn header(&self) -> Entry {
+        Entry::Remote {
+            remote: self.remote.clone(),
+        }
+    }
+}
+
+/// 記録を先頭から再生する。別のリモート向けの記録なら `None`
+///
+/// 書き込み途中で中断された末尾の行は読み飛ばす。
+fn replay(bytes: &[u8], remote: &str) -> Option<JournalData> {
+    let mut entries = bytes
+        .split(|b| *b == b'\n')
+        .map_while(|line| serde_json::from_slice::<Entry>(line).ok());
+
+    match entries.next() {
+        Some(Entry::Remote { remote: r }) if r == remote => {}
+        _ => return None,
+    }
+
+    let mut data = JournalData::default();
+    for entry in entries {
+        data.apply(entry);
+    }
+    Some(data)
+}
+
+fn completed_cutoff() -> DateTime<Utc> {
+    Utc::now() - Duration::hours(COMPLETED_TTL_HOURS)
+}
+
+fn encode_line(entry: &Entry) -> Result<Vec<u8>> {
+    let mut line = serde_json::to_vec(entry).map_err(|e| GpError::StorageError(e.to_string()))?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_journal_survives_reopen() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let journal = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        journal.mark_completed("aaa").await.unwrap();
+        journal
+            .start_multipart("bbb", "upload-1", 16, &EncodedForm::default())
+            .await
+            .unwrap();
+        journal.record_part("bbb", 1, "etag-1").await.unwrap();
+
+        let reopened = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        assert!(reopened.is_completed("aaa"));
+        let state = reopened.multipart("bbb").unwrap();
+        assert_eq!(state.upload_id, "upload-1");
+        assert_eq!(state.parts.get(&1).map(String::as_str), Some("etag-1"));
+
+        reopened.mark_completed("bbb").await.unwrap();
+        assert!(reopened.multipart("bbb").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_journal_for_other_remote_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let journal = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        journal.mark_completed("aaa").await.unwrap();
+
+        let other = UploadJournal::open(dir.path(), "file:///mnt/nas/song/").unwrap();
+        assert!(!other.is_completed("aaa"));
+    }
+
+    #[tokio::test]
+    async fn test_clear_removes_file() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let journal = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        journal.mark_completed("aaa").await.unwrap();
+        assert!(dir.path().join(JOURNAL_FILE).exists());
+
+        journal.clear().unwrap();
+        assert!(!dir.path().join(JOURNAL_FILE).exists());
+        assert!(!journal.is_completed("aaa"));
+    }
+
+    #[tokio::test]
+    async fn test_torn_last_line_is_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let journal = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        journal.mark_completed("aaa").await.unwrap();
+        journal.mark_completed("bbb").await.unwrap();
+        drop(journal);
+
+        let path = dir.path().join(JOURNAL_FILE);
+        let mut body = fs::read(&path).unwrap();
+        body.extend_from_slice(br#"{"op":"completed","ha"#);
+        fs::write(&path, body).unwrap();
+
+        let reopened = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        assert_eq!(reopened.completed_count(), 2);
+        reopened.mark_completed("ccc").await.unwrap();
+
+        let reopened = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        assert!(reopened.is_completed("ccc"));
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_multipart_is_not_resumed_after_codec_or_key_change() {
+        use crate::crypto::Keyring;
+        use crate::storage::codec::Codec;
+        use crate::storage::{BlobEncoding, BlobUpload};
+
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("song.als");
+        fs::write(&source, "<Ableton>".repeat(4096)).unwrap();
+        let blob = BlobUpload {
+            hash: "aaa".to_string(),
+            path: source,
+            offset: 0,
+            size: 9 * 4096,
+        };
+        let mut keyring = Keyring::generate();
+        let staged = |codec, keyring: &Keyring| {
+            let encoding = BlobEncoding::new(codec, Some(keyring), dir.path());
+            let blob = blob.clone();
+            async move { encoding.stage(&blob).await.unwrap().form.clone() }
+        };
+
+        let journal = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        let form = staged(Codec::Zstd, &keyring).await;
+        journal
+            .start_multipart("aaa", "upload-1", 16, &form)
+            .await
+            .unwrap();
+        journal.record_part("aaa", 1, "etag-1").await.unwrap();
+
+        // 同じ設定ならエンコード結果も同じなので、送信済みのパートを使える
+        let resumed = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        let state = resumed.multipart("aaa").unwrap();
+        assert_eq!(state.form, staged(Codec::Zstd, &keyring).await);
+
+        // 圧縮方式や鍵が変わっていれば再開しない
+        assert_ne!(state.form, staged(Codec::None, &keyring).await);
+        keyring.rotate();
+        assert_ne!(state.form, staged(Codec::Zstd, &keyring).await);
+
+        // 形式を記録していない古い記録は、どのblobとも一致しない
+        let path = dir.path().join(JOURNAL_FILE);
+        let old = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| match line.find(r#","form""#) {
+                Some(i) => format!("{}}}", &line[..i]),
+                None => line.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        fs::write(&path, old).unwrap();
+        let resumed = UploadJournal::open(dir.path(), "s3://bucket/song/").unwrap();
+        let state = resumed.multipart("aaa").unwrap();
+        assert_eq!(state.parts.len(), 1);
+        assert_ne!(state.form, form);
+    }
+
+    #[tokio::test]
+    async fn test_stale_entry_is_reuploaded_after_gc() {
+        use crate::storage::codec::Codec;
+        use crate::storage::{BlobEncoding, BlobUpload, LocalStorage, Storage};
+
+        let dir = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+        let location = storage.location("song");
+
+        fs::write(dir.path().join("a.als"), b"take 1").unwrap();
+        let blobs = [BlobUpload {
+            hash: "aaa".to_string(),
+            path: dir.path().join("a.als"),
+            offset: 0,
+            size: 6,
+        }];
+        let encoding = BlobEncoding::new(Codec::None, None, dir.path());
+
+        let journal = UploadJournal::open(dir.path(), &location).unwrap();
+        let uploaded = storage.upload_blobs("song", &blobs, &encoding, &journal);
+        assert_eq!(uploaded.await.unwrap(), 1);
+
+        // プッシュが中断したまま猶予期間が過ぎ、gcがblobを削除した
+        journal
+            .mark_completed_at("aaa", Utc::now() - Duration::hours(25))
+            .await
+            .unwrap();
+        storage
+            .delete_blobs("song", &["aaa".to_string()])
+            .await
+            .unwrap();
+
+        let resumed = UploadJournal::open(dir.path(), &location).unwrap();
+        assert!(!resumed.is_completed("aaa"));
+        assert_eq!(resumed.completed_count(), 0);
+        let uploaded = storage.upload_blobs("song", &blobs, &encoding, &resumed);
+        assert_eq!(uploaded.await.unwrap(), 1);
+        assert!(remote.path().join("song/.gp/blobs/aaa").is_file());
+    }
+}
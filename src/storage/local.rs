@@ -0,0 +1,952 @@
+use crate::crypto::Keyring;
+use crate::error::{GpError, Result};
+use crate::storage::codec::{decode_bytes, encode_bytes};
+use crate::storage::history::History;
+use crate::storage::journal::UploadJournal;
+use crate::storage::{
+    blob_progress_bar, reusable_blob, BlobEncoding, BlobSink, BlobUpload, HistoryVersion,
+    ProjectLock, Storage, StoredBlob, DEFAULT_CONCURRENCY,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Semaphore;
+
+const COPY_BUFFER_SIZE: usize = 1024 * 1024;
+const LOCK_ATTEMPTS: u32 = 100;
+const LOCK_RETRY_DELAY: Duration = Duration::from_millis(50);
+const LOCK_STALE_AFTER: Duration = Duration::from_secs(30);
+
+/// NASや外付けドライブなど、マウント済みディレクトリをリモートとして扱うバックエンド
+///
+/// レイアウトはS3と同じ `{root}/{project}/.gp/{blobs/, current_state.json, history.json}`。
+pub struct LocalStorage {
+    root: PathBuf,
+    keyring: Option<Keyring>,
+    concurrency: usize,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl AsRef<Path>) -> Result<Self> {
+        let root = root.as_ref().to_path_buf();
+
+        if !root.is_dir() {
+            return Err(GpError::DirectoryNotFound(root.display().to_string()));
+        }
+
+        Ok(Self {
+            root,
+            keyring: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        })
+    }
+
+    /// blob・メタデータの暗号化に使う鍵を設定する
+    pub fn with_keyring(mut self, keyring: Option<Keyring>) -> Self {
+        self.keyring = keyring;
+        self
+    }
+
+    /// 同時にコピー・ダウンロード・検証するblob数を設定する
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    fn gp_dir(&self, project_name: &str) -> PathBuf {
+        self.root.join(project_name).join(".gp")
+    }
+
+    fn blob_path(&self, project_name: &str, hash: &str) -> PathBuf {
+        self.gp_dir(project_name).join("blobs").join(hash)
+    }
+
+    async fn read_json<T: serde::de::DeserializeOwned>(&self, path: &Path) -> Result<Option<T>> {
+        match fs::read(path).await {
+            Ok(bytes) => self.parse_json(path, &bytes).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn parse_json<T: serde::de::DeserializeOwned>(&self, path: &Path, bytes: &[u8]) -> Result<T> {
+        let bytes = decode_bytes(bytes, self.keyring())?;
+        serde_json::from_slice(&bytes)
+            .map_err(|e| GpError::StorageError(format!("{}: {}", path.display(), e)))
+    }
+
+    async fn write_json<T: serde::Serialize>(&self, path: &Path, value: &T) -> Result<()> {
+        let body =
+            serde_json::to_vec_pretty(value).map_err(|e| GpError::StorageError(e.to_string()))?;
+        write_atomic(path, &encode_bytes(&body, self.keyring())?).await
+    }
+}
+
+/// 一時ファイルに書き込んでからリネームし、途中で中断しても壊れたファイルを残さない
+async fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).await?;
+    }
+    let tmp = temp_path(path);
+    let result = async {
+        let mut file = fs::File::create(&tmp).await?;
+        file.write_all(data).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp, path).await
+    }
+    .await;
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp).await;
+    }
+    Ok(result?)
+}
+
+/// 書き込み先と同じディレクトリに置く一時ファイルのパス
+///
+/// 同じファイルへ同時に書き込むプロセス・タスク同士が衝突しないよう、
+/// プロセスIDと連番を名前に含める。拡張子は常に `tmp`。
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let n = COUNTER.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!("{}.{}-{}.tmp", name, std::process::id(), n))
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    fn location(&self, project_name: &str) -> String {
+        format!("file://{}/", self.root.join(project_name).display())
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_ref()
+    }
+
+    fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    async fn get_remote_state(&self, project_name: &str) -> Result<HashMap<String, String>> {
+        let path = self.gp_dir(project_name).join("current_state.json");
+        Ok(self.read_json(&path).await?.unwrap_or_default())
+    }
+
+    async fn save_state(&self, project_name: &str, state: &HashMap<String, String>) -> Result<()> {
+        let path = self.gp_dir(project_name).join("current_state.json");
+        self.write_json(&path, state).await
+    }
+
+    async fn upload_blobs(
+        &self,
+        project_name: &str,
+        blobs: &[BlobUpload],
+        encoding: &BlobEncoding,
+        journal: &UploadJournal,
+    ) -> Result<usize> {
+        if blobs.is_empty() {
+            return Ok(0);
+        }
+
+        let blobs_dir = self.gp_dir(project_name).join("blobs");
+        fs::create_dir_all(&blobs_dir).await?;
+
+        let pb = blob_progress_bar(blobs.len() as u64);
+        let semaphore = Arc::new(Semaphore::new(self.concurrency()));
+        let mut handles = Vec::new();
+
+        for blob in blobs {
+            let target = blobs_dir.join(&blob.hash);
+            let blob = blob.clone();
+            let encoding = encoding.clone();
+            let journal = journal.clone();
+            let pb = pb.clone();
+            let sem = semaphore.clone();
+
+            let handle = tokio::spawn(async move {
+                let _permit = sem
+                    .acquire()
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                if journal.is_completed(&blob.hash) {
+                    pb.inc(1);
+                    return Ok::<bool, std::io::Error>(false);
+                }
+                match fs::metadata(&target).await {
+                    Ok(metadata) => {
+                        // 古いblobは最終更新を新しくし、gcの猶予期間内に収める
+                        if !reusable_blob(metadata.modified().ok().map(DateTime::<Utc>::from)) {
+                            fs::File::options()
+                                .append(true)
+                                .open(&target)
+                                .await?
+                                .into_std()
+                                .await
+                                .set_modified(std::time::SystemTime::now())?;
+                        }
+                        pb.inc(1);
+                        return Ok(false);
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+
+                let staged = encoding
+                    .stage(&blob)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+                let blob = &staged.upload;
+
+                let mut src = fs::File::open(&blob.path).await?;
+                src.seek(SeekFrom::Start(blob.offset)).await?;
+
+                let tmp = temp_path(&target);
+                let copied = async {
+                    let mut dst = fs::File::create(&tmp).await?;
+                    tokio::io::copy(&mut src.take(blob.size), &mut dst).await?;
+                    dst.sync_all().await?;
+                    fs::rename(&tmp, &target).await
+                }
+                .await;
+                if copied.is_err() {
+                    let _ = fs::remove_file(&tmp).await;
+                }
+                copied?;
+
+                journal
+                    .mark_completed(&blob.hash)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                pb.inc(1);
+                Ok(true)
+            });
+
+            handles.push(handle);
+        }
+
+        let mut uploaded_count = 0;
+        for handle in handles {
+            let was_uploaded = handle
+                .await
+                .map_err(|e| GpError::StorageError(e.to_string()))??;
+
+            if was_uploaded {
+                uploaded_count += 1;
+            }
+        }
+
+        pb.finish_and_clear();
+        Ok(uploaded_count)
+    }
+
+    async fn get_lock(&self, project_name: &str) -> Result<Option<ProjectLock>> {
+        let path = self.gp_dir(project_name).join("lock.json");
+        self.read_json(&path).await
+    }
+
+    async fn create_lock(&self, project_name: &str, lock: &ProjectLock) -> Result<()> {
+        let gp_dir = self.gp_dir(project_name);
+        fs::create_dir_all(&gp_dir).await?;
+
+        let body =
+            serde_json::to_vec_pretty(lock).map_err(|e| GpError::StorageError(e.to_string()))?;
+        let body = encode_bytes(&body, self.keyring())?;
+
+        // 書き終えた一時ファイルをハードリンクで置くことで、既存のロックを上書きせず、
+        // 他のプロセスから書きかけのロックが見えないようにする
+        let path = gp_dir.join("lock.json");
+        let tmp = temp_path(&path);
+        let linked = async {
+            let mut file = fs::File::create(&tmp).await?;
+            file.write_all(&body).await?;
+            file.sync_all().await?;
+            fs::hard_link(&tmp, &path).await
+        }
+        .await;
+        let _ = fs::remove_file(&tmp).await;
+
+        match linked {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                let holder = self.get_lock(project_name).await?;
+                Err(GpError::ProjectLocked(
+                    holder.map(|l| l.to_string()).unwrap_or_default(),
+                ))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// 比較から置き換えまでは排他ロックの中で行い、置き換えはリネームで一度に行う
+    async fn replace_lock(
+        &self,
+        project_name: &str,
+        expected: &ProjectLock,
+        lock: &ProjectLock,
+    ) -> Result<()> {
+        let path = self.gp_dir(project_name).join("lock.json");
+        let _guard = FileLock::acquire(path.with_extension("lock")).await?;
+
+        match self.get_lock(project_name).await? {
+            Some(current) if current == *expected => {}
+            Some(current) => return Err(GpError::ProjectLocked(current.to_string())),
+            None => return self.create_lock(project_name, lock).await,
+        }
+
+        let body =
+            serde_json::to_vec_pretty(lock).map_err(|e| GpError::StorageError(e.to_string()))?;
+        write_atomic(&path, &encode_bytes(&body, self.keyring())?).await
+    }
+
+    async fn delete_lock(&self, project_name: &str) -> Result<()> {
+        match fs::remove_file(self.gp_dir(project_name).join("lock.json")).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list_blobs(&self, project_name: &str) -> Result<Vec<StoredBlob>> {
+        let blobs_dir = self.gp_dir(project_name).join("blobs");
+        let mut entries = match fs::read_dir(&blobs_dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut blobs = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            // 書き込み途中の一時ファイルはblobとして扱わない
+            if path.extension().is_some_and(|ext| ext == "tmp") {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if !metadata.is_file() {
+                continue;
+            }
+            blobs.push(StoredBlob {
+                hash: entry.file_name().to_string_lossy().to_string(),
+                size: metadata.len(),
+                last_modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            });
+        }
+
+        Ok(blobs)
+    }
+
+    async fn delete_blobs(&self, project_name: &str, hashes: &[String]) -> Result<()> {
+        for hash in hashes {
+            match fs::remove_file(self.blob_path(project_name, hash)).await {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_blob(&self, project_name: &str, hash: &str, sink: &mut BlobSink) -> Result<()> {
+        let path = self.blob_path(project_name, hash);
+        let mut src = fs::File::open(&path).await.map_err(|e| {
+            if e.kind() == std::io::ErrorKind::NotFound {
+                GpError::BlobNotFound(hash.to_string())
+            } else {
+                e.into()
+            }
+        })?;
+
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+
+        loop {
+            let n = src.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            sink.write(&buf[..n]).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn load_history(&self, project_name: &str) -> Result<(Option<History>, HistoryVersion)> {
+        let path = self.gp_dir(project_name).join("history.json");
+        match fs::read(&path).await {
+            Ok(bytes) => Ok((
+                Some(self.parse_json(&path, &bytes)?),
+                HistoryVersion::Tag(content_hash(&bytes)),
+            )),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Ok((None, HistoryVersion::Missing))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_history_if(
+        &self,
+        project_name: &str,
+        history: &History,
+        expected: &HistoryVersion,
+    ) -> Result<()> {
+        let path = self.gp_dir(project_name).join("history.json");
+        fs::create_dir_all(self.gp_dir(project_name)).await?;
+        let _lock = FileLock::acquire(path.with_extension("lock")).await?;
+
+        if file_version(&path).await? != *expected {
+            return Err(GpError::HistoryConflict);
+        }
+        self.write_json(&path, history).await
+    }
+}
+
+fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// ファイル内容のハッシュを版として返す
+async fn file_version(path: &Path) -> Result<HistoryVersion> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(HistoryVersion::Tag(content_hash(&bytes))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HistoryVersion::Missing),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 比較と書き込みの間、他のプロセスが同じファイルを書き換えないようにする排他ロック
+///
+/// ロックファイルの作成（`create_new`）で取得し、dropで削除する。
+/// クラッシュで残ったロックは `LOCK_STALE_AFTER` を過ぎたら無視する。
+struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    async fn acquire(path: PathBuf) -> Result<Self> {
+        for _ in 0..LOCK_ATTEMPTS {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => return Ok(Self { path }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    if is_stale(&path).await {
+                        let _ = fs::remove_file(&path).await;
+                    } else {
+                        tokio::time::sleep(LOCK_RETRY_DELAY).await;
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Err(GpError::StorageError(format!(
+            "ロックを取得できませんでした: {}",
+            path.display()
+        )))
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+async fn is_stale(path: &Path) -> bool {
+    fs::metadata(path)
+        .await
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age > LOCK_STALE_AFTER)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scanner::ScannedFile;
+    use crate::storage::codec::Codec;
+    use crate::storage::{ChunkRef, DownloadTarget, Snapshot, DEFAULT_BRANCH};
+    use indicatif::ProgressBar;
+
+    fn sha256(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    fn scanned(dir: &Path, name: &str, content: &[u8]) -> ScannedFile {
+        let path = dir.join(name);
+        std::fs::write(&path, content).unwrap();
+        ScannedFile {
+            relative_path: PathBuf::from(name),
+            absolute_path: path,
+            size: content.len() as u64,
+            hash: sha256(content),
+        }
+    }
+
+    fn whole_files(files: &[ScannedFile]) -> Vec<BlobUpload> {
+        files.iter().map(BlobUpload::whole_file).collect()
+    }
+
+    /// 圧縮も暗号化もしないエンコード
+    fn raw() -> BlobEncoding {
+        BlobEncoding::new(Codec::None, None, Path::new(""))
+    }
+
+    #[tokio::test]
+    async fn test_blob_roundtrip_and_dedup() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let blobs = whole_files(&[scanned(work.path(), "a.als", b"take 1")]);
+        let aaa = blobs[0].hash.clone();
+        let journal = UploadJournal::in_memory();
+        assert_eq!(
+            storage
+                .upload_blobs("song", &blobs, &raw(), &journal)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(journal.is_completed(&aaa));
+        assert_eq!(
+            storage
+                .upload_blobs("song", &blobs, &raw(), &UploadJournal::in_memory())
+                .await
+                .unwrap(),
+            0
+        );
+
+        assert!(remote.path().join("song/.gp/blobs").join(&aaa).is_file());
+        let dest = work.path().join("restored.als");
+        std::fs::File::create(&dest).unwrap();
+        let target = DownloadTarget {
+            hash: aaa.clone(),
+            path: dest.clone(),
+            offset: 0,
+            size: 6,
+        };
+        let pb = ProgressBar::hidden();
+        assert_eq!(
+            storage.download_blob("song", &target, &pb).await.unwrap(),
+            6
+        );
+        assert_eq!(std::fs::read(&dest).unwrap(), b"take 1");
+
+        let missing = DownloadTarget {
+            hash: "missing".to_string(),
+            ..target.clone()
+        };
+        assert!(matches!(
+            storage.download_blob("song", &missing, &pb).await,
+            Err(GpError::BlobNotFound(_))
+        ));
+
+        std::fs::write(remote.path().join("song/.gp/blobs").join(&aaa), b"take 2").unwrap();
+        assert!(matches!(
+            storage.download_blob("song", &target, &pb).await,
+            Err(GpError::HashMismatch { .. })
+        ));
+        assert!(matches!(
+            storage.verify_blob("song", &aaa, &pb).await,
+            Err(GpError::HashMismatch { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_reused_old_blob_is_refreshed() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let blobs = whole_files(&[scanned(work.path(), "a.als", b"take 1")]);
+        let journal = UploadJournal::in_memory();
+        storage
+            .upload_blobs("song", &blobs, &raw(), &journal)
+            .await
+            .unwrap();
+
+        // 中断したプッシュの残りで、gcの猶予期間を過ぎている
+        let stored = remote.path().join("song/.gp/blobs").join(&blobs[0].hash);
+        let two_days_ago = std::time::SystemTime::now() - Duration::from_secs(48 * 3600);
+        std::fs::File::options()
+            .append(true)
+            .open(&stored)
+            .unwrap()
+            .set_modified(two_days_ago)
+            .unwrap();
+
+        let journal = UploadJournal::in_memory();
+        let encoding = raw();
+        let uploaded = storage.upload_blobs("song", &blobs, &encoding, &journal);
+        assert_eq!(uploaded.await.unwrap(), 0);
+
+        let blobs = storage.list_blobs("song").await.unwrap();
+        let cutoff = Utc::now() - chrono::Duration::hours(24);
+        assert!(blobs[0].last_modified.unwrap() > cutoff);
+    }
+
+    #[tokio::test]
+    async fn test_download_blobs_restores_snapshot() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let restore = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let files = vec![
+            scanned(work.path(), "a.als", b"take 1"),
+            scanned(work.path(), "b.wav", b"kick"),
+        ];
+        storage
+            .upload_blobs(
+                "song",
+                &whole_files(&files),
+                &raw(),
+                &UploadJournal::in_memory(),
+            )
+            .await
+            .unwrap();
+
+        let mut snapshot_files = HashMap::new();
+        snapshot_files.insert("a.als".to_string(), files[0].hash.clone());
+        snapshot_files.insert("Samples/b.wav".to_string(), files[1].hash.clone());
+        let snapshot = Snapshot::new(
+            None,
+            snapshot_files,
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            2,
+        );
+
+        let targets = DownloadTarget::for_snapshot(&snapshot, restore.path());
+        storage.download_blobs("song", &targets).await.unwrap();
+
+        assert_eq!(
+            std::fs::read(restore.path().join("a.als")).unwrap(),
+            b"take 1"
+        );
+        assert_eq!(
+            std::fs::read(restore.path().join("Samples/b.wav")).unwrap(),
+            b"kick"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_download_keeps_existing_files() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let restore = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let file = scanned(work.path(), "a.als", b"take 2");
+        storage
+            .upload_blobs(
+                "song",
+                &whole_files(std::slice::from_ref(&file)),
+                &raw(),
+                &UploadJournal::in_memory(),
+            )
+            .await
+            .unwrap();
+        std::fs::write(restore.path().join("a.als"), b"take 1").unwrap();
+        std::fs::write(restore.path().join("b.wav"), b"kick").unwrap();
+
+        let mut snapshot_files = HashMap::new();
+        snapshot_files.insert("a.als".to_string(), file.hash.clone());
+        snapshot_files.insert("b.wav".to_string(), "missing".to_string());
+        let snapshot = Snapshot::new(
+            None,
+            snapshot_files,
+            HashMap::new(),
+            HashMap::new(),
+            None,
+            2,
+        );
+
+        let targets = DownloadTarget::for_snapshot(&snapshot, restore.path());
+        assert!(storage.download_blobs("song", &targets).await.is_err());
+
+        assert_eq!(
+            std::fs::read(restore.path().join("a.als")).unwrap(),
+            b"take 1"
+        );
+        assert_eq!(
+            std::fs::read(restore.path().join("b.wav")).unwrap(),
+            b"kick"
+        );
+        assert_eq!(std::fs::read_dir(restore.path()).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_chunked_file_roundtrip() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let restore = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let file = scanned(work.path(), "mix.wav", b"intro-verse-chorus");
+        let (c1, c2) = (sha256(b"intro-"), sha256(b"verse-chorus"));
+        let blobs = vec![
+            BlobUpload {
+                hash: c1.clone(),
+                path: file.absolute_path.clone(),
+                offset: 0,
+                size: 6,
+            },
+            BlobUpload {
+                hash: c2.clone(),
+                path: file.absolute_path.clone(),
+                offset: 6,
+                size: 12,
+            },
+        ];
+        storage
+            .upload_blobs("song", &blobs, &raw(), &UploadJournal::in_memory())
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read(remote.path().join("song/.gp/blobs").join(&c2)).unwrap(),
+            b"verse-chorus"
+        );
+
+        let mut files = HashMap::new();
+        files.insert("mix.wav".to_string(), file.hash.clone());
+        let mut chunks = HashMap::new();
+        chunks.insert(
+            file.hash.clone(),
+            vec![
+                ChunkRef { hash: c1, size: 6 },
+                ChunkRef { hash: c2, size: 12 },
+            ],
+        );
+        let snapshot = Snapshot::new(None, files, HashMap::new(), chunks, None, 1);
+
+        let targets = DownloadTarget::for_snapshot(&snapshot, restore.path());
+        assert_eq!(targets.len(), 2);
+        storage.download_blobs("song", &targets).await.unwrap();
+
+        assert_eq!(
+            std::fs::read(restore.path().join("mix.wav")).unwrap(),
+            b"intro-verse-chorus"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_state_and_history_roundtrip() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        assert!(storage.get_remote_state("song").await.unwrap().is_empty());
+        assert!(storage.get_history("song").await.unwrap().is_none());
+
+        let file = scanned(work.path(), "a.als", b"take 1");
+        let state = HashMap::from([("a.als".to_string(), file.hash.clone())]);
+        storage.save_state("song", &state).await.unwrap();
+        assert_eq!(storage.get_remote_state("song").await.unwrap(), state);
+
+        let history = storage
+            .update_history("song", &mut |history| {
+                history.add_snapshot_to(DEFAULT_BRANCH, snapshot_of(&state, history.head.clone()));
+                Ok(())
+            })
+            .await
+            .unwrap();
+        let restored = storage.get_history("song").await.unwrap().unwrap();
+        assert_eq!(restored.head, history.head);
+    }
+
+    fn snapshot_of(files: &HashMap<String, String>, parent_id: Option<String>) -> Snapshot {
+        Snapshot::new(
+            None,
+            files.clone(),
+            HashMap::new(),
+            HashMap::new(),
+            parent_id,
+            1,
+        )
+    }
+
+    #[tokio::test]
+    async fn test_history_write_conflict_is_detected() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+        let files = HashMap::new();
+
+        let (_, version) = storage.load_history("song").await.unwrap();
+        assert_eq!(version, HistoryVersion::Missing);
+
+        let mut first = History::new("song");
+        first.add_snapshot_to(DEFAULT_BRANCH, snapshot_of(&files, None));
+        storage
+            .save_history_if("song", &first, &version)
+            .await
+            .unwrap();
+
+        // 同じ版を元にした2人目の書き込みは上書きしない
+        let mut second = History::new("song");
+        second.add_snapshot_to(DEFAULT_BRANCH, snapshot_of(&files, None));
+        assert!(matches!(
+            storage.save_history_if("song", &second, &version).await,
+            Err(GpError::HistoryConflict)
+        ));
+        let stored = storage.get_history("song").await.unwrap().unwrap();
+        assert_eq!(stored.head, first.head);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_history_updates_keep_every_snapshot() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+        let files = HashMap::new();
+
+        let push = || async {
+            storage
+                .update_history("song", &mut |history| {
+                    history
+                        .add_snapshot_to(DEFAULT_BRANCH, snapshot_of(&files, history.head.clone()));
+                    Ok(())
+                })
+                .await
+                .unwrap()
+        };
+        tokio::join!(push(), push(), push());
+
+        let history = storage.get_history("song").await.unwrap().unwrap();
+        assert_eq!(history.snapshots.len(), 3);
+        for pair in history.snapshots.windows(2) {
+            assert_eq!(pair[1].parent_id.as_ref(), Some(&pair[0].id));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_delete_blobs() {
+        let work = tempfile::tempdir().unwrap();
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+        assert!(storage.list_blobs("song").await.unwrap().is_empty());
+
+        let files = vec![
+            scanned(work.path(), "a.als", b"take 1"),
+            scanned(work.path(), "b.wav", b"kick"),
+        ];
+        storage
+            .upload_blobs(
+                "song",
+                &whole_files(&files),
+                &raw(),
+                &UploadJournal::in_memory(),
+            )
+            .await
+            .unwrap();
+        let partial = temp_path(&remote.path().join("song/.gp/blobs").join(&files[0].hash));
+        std::fs::write(partial, b"x").unwrap();
+
+        let mut blobs = storage.list_blobs("song").await.unwrap();
+        blobs.sort_by(|a, b| a.hash.cmp(&b.hash));
+        let mut expected = vec![(files[0].hash.clone(), 6), (files[1].hash.clone(), 4)];
+        expected.sort();
+        assert_eq!(
+            blobs
+                .iter()
+                .map(|b| (b.hash.clone(), b.size))
+                .collect::<Vec<_>>(),
+            expected
+        );
+        assert!(blobs.iter().all(|b| b.last_modified.is_some()));
+
+        storage
+            .delete_blobs("song", &[files[0].hash.clone(), "missing".to_string()])
+            .await
+            .unwrap();
+        let blobs = storage.list_blobs("song").await.unwrap();
+        assert_eq!(blobs.len(), 1);
+        assert_eq!(blobs[0].hash, files[1].hash);
+    }
+
+    #[tokio::test]
+    async fn test_lock_is_not_overwritten() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+        assert!(storage.get_lock("song").await.unwrap().is_none());
+
+        let mine = ProjectLock::current();
+        storage.create_lock("song", &mine).await.unwrap();
+        assert!(storage.get_lock("song").await.unwrap().unwrap().is_mine());
+
+        let other = ProjectLock {
+            user: "someone-else".to_string(),
+            ..mine.clone()
+        };
+        assert!(!other.is_mine());
+        assert!(matches!(
+            storage.create_lock("song", &other).await,
+            Err(GpError::ProjectLocked(_))
+        ));
+        assert_eq!(storage.get_lock("song").await.unwrap(), Some(mine));
+        let entries: Vec<_> = std::fs::read_dir(remote.path().join("song/.gp"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["lock.json"]);
+
+        storage.delete_lock("song").await.unwrap();
+        storage.delete_lock("song").await.unwrap();
+        storage.create_lock("song", &other).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_forced_lock_replaces_only_the_lock_that_was_seen() {
+        let remote = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(remote.path()).unwrap();
+
+        let mine = ProjectLock::current();
+        let first = ProjectLock {
+            user: "first".to_string(),
+            ..mine.clone()
+        };
+        let second = ProjectLock {
+            user: "second".to_string(),
+            ..mine.clone()
+        };
+        storage.create_lock("song", &first).await.unwrap();
+
+        // 2人が同じロックを見て同時に --force した場合、後の人は先の人のロックを消さない
+        storage.replace_lock("song", &first, &second).await.unwrap();
+        assert!(matches!(
+            storage.replace_lock("song", &first, &mine).await,
+            Err(GpError::ProjectLocked(_))
+        ));
+        assert_eq!(
+            storage.get_lock("song").await.unwrap(),
+            Some(second.clone())
+        );
+
+        // 置き換える前に解除されていれば、そのまま取る
+        storage.delete_lock("song").await.unwrap();
+        storage.replace_lock("song", &second, &mine).await.unwrap();
+        assert_eq!(storage.get_lock("song").await.unwrap(), Some(mine));
+        let entries: Vec<_> = std::fs::read_dir(remote.path().join("song/.gp"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, vec!["lock.json"]);
+    }
+}
@@ -0,0 +1,40 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// 編集中のプロジェクトを他の人がプッシュしないようにするロック（`{project}/.gp/lock.json`）
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProjectLock {
+    pub user: String,
+    pub host: String,
+    pub locked_at: DateTime<Utc>,
+}
+
+impl ProjectLock {
+    /// このマシンの現在のユーザーによるロック
+    pub fn current() -> Self {
+        Self {
+            user: whoami::username(),
+            host: whoami::fallible::hostname().unwrap_or_else(|_| "unknown".to_string()),
+            locked_at: Utc::now(),
+        }
+    }
+
+    /// このマシンの現在のユーザーが持っているロックか
+    pub fn is_mine(&self) -> bool {
+        let me = Self::current();
+        self.user == me.user && self.host == me.host
+    }
+}
+
+impl fmt::Display for ProjectLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}@{}（{}）",
+            self.user,
+            self.host,
+            self.locked_at.format("%Y-%m-%d %H:%M:%S UTC")
+        )
+    }
+}
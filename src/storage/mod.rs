@@ -1,5 +1,418 @@
+pub mod codec;
 pub mod history;
+pub mod journal;
+pub mod local;
+pub mod lock;
+pub mod remote;
 pub mod s3;
 
-pub use history::{History, Snapshot};
-pub use s3::{extract_project_name, S3Storage};
+use crate::crypto::Keyring;
+use crate::error::{GpError, Result};
+use crate::scanner::ScannedFile;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::future::{join_all, try_join_all};
+use indicatif::{ProgressBar, ProgressStyle};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::sync::Semaphore;
+
+pub use codec::{BlobEncoding, BlobSink};
+pub use history::{ChunkRef, History, Snapshot, DEFAULT_BRANCH};
+pub use journal::UploadJournal;
+pub use local::LocalStorage;
+pub use lock::ProjectLock;
+pub use remote::{open, open_default, open_with_keyring};
+pub use s3::S3Storage;
+
+/// 同時に転送するblob数の既定値（`.gp/config.toml` の `concurrency` で変更可）
+pub const DEFAULT_CONCURRENCY: usize = 10;
+/// 履歴の更新が他のプッシュと競合した場合の再試行回数
+const MAX_HISTORY_ATTEMPTS: u32 = 5;
+const HISTORY_RETRY_BASE_DELAY: Duration = Duration::from_millis(200);
+/// リモートに既にあるblobを、送り直さずにそのまま使える最終更新からの時間
+///
+/// 履歴から参照されていないblob（中断したプッシュや `gp prune` の残り）は、`gp gc` の猶予期間を
+/// 過ぎると削除される。これより古いblobは送り直して最終更新を新しくし、プッシュが履歴に
+/// 載せるまでの間にgcに消されないようにする。
+const BLOB_REUSE_HOURS: i64 = 1;
+
+/// アップロードするblob1件分（ファイル全体、またはチャンクとしてのファイルの一部）
+#[derive(Debug, Clone)]
+pub struct BlobUpload {
+    pub hash: String,
+    pub path: PathBuf,
+    pub offset: u64,
+    pub size: u64,
+}
+
+impl BlobUpload {
+    pub fn whole_file(file: &ScannedFile) -> Self {
+        Self {
+            hash: file.hash.clone(),
+            path: file.absolute_path.clone(),
+            offset: 0,
+            size: file.size,
+        }
+    }
+}
+
+/// 復元するblob1件分。チャンク分割されたファイルは `offset` の位置に書き込む
+#[derive(Debug, Clone)]
+pub struct DownloadTarget {
+    pub hash: String,
+    pub path: PathBuf,
+    pub offset: u64,
+    /// 不明な場合は0（古い履歴）
+    pub size: u64,
+}
+
+impl DownloadTarget {
+    /// スナップショットの全ファイルを `root` 以下に復元する対象一覧
+    pub fn for_snapshot(snapshot: &Snapshot, root: &Path) -> Vec<Self> {
+        snapshot
+            .files
+            .keys()
+            .flat_map(|relative_path| {
+                Self::for_file(snapshot, relative_path, root.join(relative_path))
+            })
+            .collect()
+    }
+
+    /// スナップショット内の1ファイルを `dest` に復元する対象一覧
+    pub fn for_file(snapshot: &Snapshot, relative_path: &str, dest: PathBuf) -> Vec<Self> {
+        let Some(hash) = snapshot.files.get(relative_path) else {
+            return Vec::new();
+        };
+
+        match snapshot.chunks.get(hash) {
+            Some(chunks) => {
+                let mut offset = 0;
+                chunks
+                    .iter()
+                    .map(|chunk| {
+                        let target = Self {
+                            hash: chunk.hash.clone(),
+                            path: dest.clone(),
+                            offset,
+                            size: chunk.size,
+                        };
+                        offset += chunk.size;
+                        target
+                    })
+                    .collect()
+            }
+            None => vec![Self {
+                hash: hash.clone(),
+                path: dest,
+                offset: 0,
+                size: snapshot.sizes.get(relative_path).copied().unwrap_or(0),
+            }],
+        }
+    }
+}
+
+/// ダウンロード中の内容を書き込む一時ファイル（`dest` と同じディレクトリの隠しファイル）
+fn download_temp_path(dest: &Path) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{}.gp-download", name))
+}
+
+/// 最終更新が `BLOB_REUSE_HOURS` 以内で、送り直さずに使える既存のblobか
+fn reusable_blob(last_modified: Option<DateTime<Utc>>) -> bool {
+    let cutoff = Utc::now() - chrono::Duration::hours(BLOB_REUSE_HOURS);
+    last_modified.is_some_and(|modified| modified > cutoff)
+}
+
+/// リモートに保存されているblob1件分
+#[derive(Debug, Clone)]
+pub struct StoredBlob {
+    pub hash: String,
+    /// 保存されているバイト数（圧縮・暗号化後）
+    pub size: u64,
+    pub last_modified: Option<DateTime<Utc>>,
+}
+
+/// 読み込んだ時点の `history.json` の版。条件付き書き込みで他者の更新を検出するのに使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryVersion {
+    /// まだ存在しない
+    Missing,
+    /// S3ならETag、ローカルなら内容のハッシュ
+    Tag(String),
+}
+
+/// リモートストレージのバックエンド
+///
+/// `{project}/.gp/` 以下のblob・状態・履歴の読み書きを抽象化する。
+/// コマンドはこのトレイト越しにのみリモートへアクセスする。
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// 人間向けのリモート位置（例: `s3://bucket/project/`）
+    fn location(&self, project_name: &str) -> String;
+
+    /// blob・メタデータの暗号化に使う鍵。`None` なら平文で保存する
+    fn keyring(&self) -> Option<&Keyring>;
+
+    /// 同時に転送するblob数
+    fn concurrency(&self) -> usize;
+
+    async fn get_remote_state(&self, project_name: &str) -> Result<HashMap<String, String>>;
+
+    /// `current_state.json`（ファイルパス → ハッシュ）を保存する
+    async fn save_state(&self, project_name: &str, state: &HashMap<String, String>) -> Result<()>;
+
+    /// `current_state.json` を履歴の最新スナップショットに合わせる
+    ///
+    /// 書き込みの間に他のプッシュが履歴を進めていたら、新しい最新スナップショットで書き直す。
+    async fn sync_state(&self, project_name: &str, mut history: History) -> Result<()> {
+        loop {
+            let state = history
+                .head_snapshot()
+                .map(|s| s.files.clone())
+                .unwrap_or_default();
+            self.save_state(project_name, &state).await?;
+
+            let latest = self
+                .get_history(project_name)
+                .await?
+                .unwrap_or_else(|| History::new(project_name));
+            if latest.head == history.head {
+                return Ok(());
+            }
+            history = latest;
+        }
+    }
+
+    /// 未アップロードのblobのみ送信し、新規にアップロードした件数を返す
+    ///
+    /// 各blobはリモートに無いことを確認してから `encoding` でエンコードし、送信後に一時ファイルを消す。
+    /// 既にあっても最終更新が古いblobは、`gp gc` に消されないよう最終更新を新しくする。
+    /// 確認・送信済みのblobは `journal` に記録され、中断後の再実行では飛ばされる。
+    async fn upload_blobs(
+        &self,
+        project_name: &str,
+        blobs: &[BlobUpload],
+        encoding: &BlobEncoding,
+        journal: &UploadJournal,
+    ) -> Result<usize>;
+
+    /// プロジェクトのロックを読み込む
+    async fn get_lock(&self, project_name: &str) -> Result<Option<ProjectLock>>;
+
+    /// ロックが無ければ作成する。既にロックされていれば上書きせず `GpError::ProjectLocked` を返す
+    async fn create_lock(&self, project_name: &str, lock: &ProjectLock) -> Result<()>;
+
+    /// ロックが `expected` のままなら `lock` に置き換える（`gp lock --force`）
+    ///
+    /// その間に他の人がロックを取り直していれば上書きせず `GpError::ProjectLocked` を返す。
+    /// ロックが既に解除されていれば `create_lock` と同じ。
+    async fn replace_lock(
+        &self,
+        project_name: &str,
+        expected: &ProjectLock,
+        lock: &ProjectLock,
+    ) -> Result<()>;
+
+    /// ロックを削除する。ロックされていなければ何もしない
+    async fn delete_lock(&self, project_name: &str) -> Result<()>;
+
+    /// `{project}/.gp/blobs/` 以下に保存されているblobの一覧
+    async fn list_blobs(&self, project_name: &str) -> Result<Vec<StoredBlob>>;
+
+    /// blobを削除する。存在しないblobは無視する
+    async fn delete_blobs(&self, project_name: &str, hashes: &[String]) -> Result<()>;
+
+    /// blobをストリームで読み出し、受信したバイト列を順に `sink` へ渡す
+    async fn read_blob(&self, project_name: &str, hash: &str, sink: &mut BlobSink) -> Result<()>;
+
+    /// blobを `target.path` の `target.offset` の位置に直接ストリームで書き込み、
+    /// 書き込んだバイト数を返す。ファイルは事前に作成されている必要がある
+    ///
+    /// 圧縮・暗号化されたblobは `BlobSink` で復号・展開しながら書き込み、展開後のバイト数を随時 `pb` に加算する。
+    /// 展開後の内容がblob名のハッシュと一致しなければ `GpError::HashMismatch` を返す。
+    async fn download_blob(
+        &self,
+        project_name: &str,
+        target: &DownloadTarget,
+        pb: &ProgressBar,
+    ) -> Result<u64> {
+        let mut sink = BlobSink::open(target, self.keyring(), pb);
+        self.read_blob(project_name, &target.hash, &mut sink).await?;
+        sink.finish().await
+    }
+
+    /// blobをダウンロードしてハッシュだけを検証する（ファイルには書き込まない）
+    async fn verify_blob(&self, project_name: &str, hash: &str, pb: &ProgressBar) -> Result<u64> {
+        let mut sink = BlobSink::verify_only(hash, self.keyring(), pb);
+        self.read_blob(project_name, hash, &mut sink).await?;
+        sink.finish().await
+    }
+
+    /// 複数のblobをSemaphore制限付きで並列にダウンロードする
+    ///
+    /// 受信は並行に進め、各blobの復号・展開と書き込みはblobごとに `spawn_blocking` のタスクで行う。
+    /// 各ファイルは隣の一時ファイルに書き込み、すべてのblobが揃ってから置き換える。
+    /// 途中で失敗しても既存のファイルは書きかけの状態にならない。
+    async fn download_blobs(&self, project_name: &str, targets: &[DownloadTarget]) -> Result<()> {
+        let total: u64 = targets.iter().map(|t| t.size).sum();
+        let pb = byte_progress_bar(total);
+        let semaphore = Semaphore::new(self.concurrency());
+
+        // チャンクは同じファイルの別々の位置に並列で書き込むため、先に空の一時ファイルを用意する
+        let paths: HashSet<&Path> = targets.iter().map(|t| t.path.as_path()).collect();
+        let temps: HashMap<&Path, PathBuf> = paths
+            .into_iter()
+            .map(|p| (p, download_temp_path(p)))
+            .collect();
+        for temp in temps.values() {
+            if let Some(parent) = temp.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            tokio::fs::File::create(temp).await?;
+        }
+
+        let tasks = targets.iter().map(|target| {
+            let pb = &pb;
+            let semaphore = &semaphore;
+            let target = DownloadTarget {
+                path: temps[target.path.as_path()].clone(),
+                ..target.clone()
+            };
+            async move {
+                let _permit = semaphore
+                    .acquire()
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                let written = self.download_blob(project_name, &target, pb).await?;
+                if target.size == 0 {
+                    pb.inc_length(written);
+                }
+                Ok::<(), GpError>(())
+            }
+        });
+
+        let result = try_join_all(tasks).await;
+        pb.finish_and_clear();
+        if let Err(e) = result {
+            for temp in temps.values() {
+                let _ = tokio::fs::remove_file(temp).await;
+            }
+            return Err(e);
+        }
+
+        for (path, temp) in &temps {
+            tokio::fs::File::open(temp).await?.sync_all().await?;
+            tokio::fs::rename(temp, path).await?;
+        }
+        Ok(())
+    }
+
+    /// 複数のblobを並列に検証し、問題のあったblobとそのエラーを返す
+    ///
+    /// 最初の失敗で止めず、すべてのblobを検証する。
+    async fn verify_blobs(
+        &self,
+        project_name: &str,
+        blobs: &BTreeMap<String, u64>,
+    ) -> Vec<(String, GpError)> {
+        let pb = byte_progress_bar(blobs.values().sum());
+        let semaphore = Semaphore::new(self.concurrency());
+
+        let tasks = blobs.iter().map(|(hash, &size)| {
+            let pb = &pb;
+            let semaphore = &semaphore;
+            async move {
+                let result = match semaphore.acquire().await {
+                    Ok(_permit) => self.verify_blob(project_name, hash, pb).await,
+                    Err(e) => Err(std::io::Error::other(e.to_string()).into()),
+                };
+                match result {
+                    Ok(written) if size == 0 => {
+                        pb.inc_length(written);
+                        None
+                    }
+                    Ok(_) => None,
+                    Err(e) => Some((hash.clone(), e)),
+                }
+            }
+        });
+
+        let failures = join_all(tasks).await.into_iter().flatten().collect();
+        pb.finish_and_clear();
+        failures
+    }
+
+    /// `history.json` とその版を読み込む
+    async fn load_history(&self, project_name: &str) -> Result<(Option<History>, HistoryVersion)>;
+
+    async fn get_history(&self, project_name: &str) -> Result<Option<History>> {
+        Ok(self.load_history(project_name).await?.0)
+    }
+
+    /// `history.json` が `expected` の版から変わっていなければ保存する
+    ///
+    /// 他の誰かが先に更新していた場合は上書きせず `GpError::HistoryConflict` を返す。
+    async fn save_history_if(
+        &self,
+        project_name: &str,
+        history: &History,
+        expected: &HistoryVersion,
+    ) -> Result<()>;
+
+    /// 履歴を読み込み、`update` で書き換えて条件付きで保存し、保存した履歴を返す
+    ///
+    /// 競合した場合は最新の履歴を読み直して `update` からやり直す。
+    /// 履歴がまだ無ければ空の履歴に対して `update` を呼ぶ。
+    async fn update_history(
+        &self,
+        project_name: &str,
+        update: &mut (dyn for<'h> FnMut(&'h mut History) -> Result<()> + Send),
+    ) -> Result<History> {
+        let mut attempt = 1;
+
+        loop {
+            let (history, version) = self.load_history(project_name).await?;
+            let mut history = history.unwrap_or_else(|| History::new(project_name));
+            update(&mut history)?;
+
+            match self.save_history_if(project_name, &history, &version).await {
+                Ok(()) => return Ok(history),
+                Err(GpError::HistoryConflict) if attempt < MAX_HISTORY_ATTEMPTS => {
+                    tokio::time::sleep(HISTORY_RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// blob転送用の件数ベースのプログレスバー
+pub(crate) fn blob_progress_bar(len: u64) -> ProgressBar {
+    let pb = ProgressBar::new(len);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} blobs")
+            .expect("プログレスバーのテンプレートエラー")
+            .progress_chars("#>-"),
+    );
+    pb
+}
+
+/// 転送量ベースのプログレスバー
+pub(crate) fn byte_progress_bar(total: u64) -> ProgressBar {
+    let pb = ProgressBar::new(total);
+    pb.set_style(
+        ProgressStyle::default_bar()
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
+            .expect("プログレスバーのテンプレートエラー")
+            .progress_chars("#>-"),
+    );
+    pb
+}
@@ -0,0 +1,142 @@
+use crate::config::ProjectConfig;
+use crate::crypto::Keyring;
+use crate::error::{GpError, Result};
+use crate::storage::{LocalStorage, S3Storage, Storage};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// リモートの接続先（`s3://bucket` または `file:///path` 形式）
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Remote {
+    S3 { bucket: String },
+    Local { root: PathBuf },
+}
+
+impl FromStr for Remote {
+    type Err = GpError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(bucket) = s.strip_prefix("s3://") {
+            let bucket = bucket.trim_end_matches('/');
+            if bucket.is_empty() || bucket.contains('/') {
+                return Err(GpError::InvalidRemote(s.to_string()));
+            }
+            return Ok(Remote::S3 {
+                bucket: bucket.to_string(),
+            });
+        }
+
+        if let Some(path) = s.strip_prefix("file://") {
+            if !path.starts_with('/') {
+                return Err(GpError::InvalidRemote(s.to_string()));
+            }
+            return Ok(Remote::Local {
+                root: PathBuf::from(path),
+            });
+        }
+
+        Err(GpError::InvalidRemote(s.to_string()))
+    }
+}
+
+impl Remote {
+    pub async fn connect(
+        &self,
+        keyring: Option<Keyring>,
+        concurrency: usize,
+    ) -> Result<Box<dyn Storage>> {
+        match self {
+            Remote::S3 { bucket } => Ok(Box::new(
+                S3Storage::new(bucket.clone())
+                    .await?
+                    .with_keyring(keyring)
+                    .with_concurrency(concurrency),
+            )),
+            Remote::Local { root } => Ok(Box::new(
+                LocalStorage::new(root)?
+                    .with_keyring(keyring)
+                    .with_concurrency(concurrency),
+            )),
+        }
+    }
+}
+
+/// プロジェクト設定（`project_root/.gp/config.toml` とユーザー設定）の既定のリモートを開く
+///
+/// `GROOVEPUSH_REMOTE` 環境変数があれば設定より優先する。
+/// 鍵ファイルがあれば、そのバックエンドでの読み書きは暗号化される。
+pub async fn open_default(project_root: &Path) -> Result<Box<dyn Storage>> {
+    open(project_root, None).await
+}
+
+/// `remote`（`gp remote add` で付けた名前、またはURL）を開く。省略した場合は既定のリモート
+///
+/// 設定ファイルの無い従来のプロジェクトでは、先に `.gp/config.toml` を書き出す。
+pub async fn open(project_root: &Path, remote: Option<&str>) -> Result<Box<dyn Storage>> {
+    open_with_keyring(project_root, remote, Keyring::load_default()?).await
+}
+
+/// 読み込み済みの鍵で `remote` を開く。複数のリモートを開くときにパスフレーズを何度も尋ねない
+pub async fn open_with_keyring(
+    project_root: &Path,
+    remote: Option<&str>,
+    keyring: Option<Keyring>,
+) -> Result<Box<dyn Storage>> {
+    if let Some(migrated) = ProjectConfig::migrate_legacy(project_root)? {
+        println!(
+            "設定ファイルを作成しました: {}（プロジェクト名: {}、リモート: {}）",
+            ProjectConfig::path(project_root).display(),
+            migrated.name.as_deref().unwrap_or_default(),
+            migrated.remote.as_deref().unwrap_or_default()
+        );
+    }
+    let config = ProjectConfig::load(project_root)?;
+    config
+        .remote(remote)?
+        .connect(keyring, config.concurrency())
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_s3_remote() {
+        let remote: Remote = "s3://my-bucket".parse().unwrap();
+        assert_eq!(
+            remote,
+            Remote::S3 {
+                bucket: "my-bucket".to_string()
+            }
+        );
+
+        let remote: Remote = "s3://my-bucket/".parse().unwrap();
+        assert_eq!(
+            remote,
+            Remote::S3 {
+                bucket: "my-bucket".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_file_remote() {
+        let remote: Remote = "file:///mnt/nas/groovepush".parse().unwrap();
+        assert_eq!(
+            remote,
+            Remote::Local {
+                root: PathBuf::from("/mnt/nas/groovepush")
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid_remote() {
+        assert!("file://relative/path".parse::<Remote>().is_err());
+        assert!("s3://".parse::<Remote>().is_err());
+        assert!("s3://bucket/prefix".parse::<Remote>().is_err());
+        assert!("ftp://host".parse::<Remote>().is_err());
+        assert!("my-bucket".parse::<Remote>().is_err());
+    }
+}
@@ -1,38 +1,91 @@
+use crate::crypto::Keyring;
 use crate::error::{GpError, Result};
-use crate::scanner::ScannedFile;
+use crate::storage::codec::{decode_bytes, encode_bytes, StagedBlob};
 use crate::storage::history::History;
-use aws_sdk_s3::primitives::ByteStream;
+use crate::storage::journal::{MultipartState, UploadJournal};
+use crate::storage::{
+    blob_progress_bar, reusable_blob, BlobEncoding, BlobSink, BlobUpload, HistoryVersion,
+    ProjectLock, Storage, StoredBlob, DEFAULT_CONCURRENCY,
+};
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::{ByteStream, Length};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart, Delete, ObjectIdentifier};
 use aws_sdk_s3::Client;
-use indicatif::{ProgressBar, ProgressStyle};
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
-use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
-use tokio::fs;
+use std::time::Duration;
 use tokio::sync::Semaphore;
 
-const DEFAULT_BUCKET: &str = "groovepush-bucket";
-const MAX_CONCURRENT_UPLOADS: usize = 10;
+/// このサイズ以上のファイルはマルチパートでアップロードする
+const DEFAULT_MULTIPART_THRESHOLD: u64 = 64 * 1024 * 1024;
+/// S3の制約はパート最小5MiB・最大10,000個。リクエスト数と再送の単位の
+/// バランスから、下限より大きい16MiBを最小パートサイズにしている
+const MIN_PART_SIZE: u64 = 16 * 1024 * 1024;
+const MAX_PARTS: u64 = 10_000;
+/// 1ファイル内で同時に送信するパート数
+const MAX_CONCURRENT_PARTS: usize = 4;
+/// DeleteObjectsで一度に削除できるキーの上限
+const MAX_DELETE_BATCH: usize = 1000;
+const MAX_PART_ATTEMPTS: u32 = 5;
+const PART_RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
 
 pub struct S3Storage {
     client: Client,
     bucket: String,
+    multipart_threshold: u64,
+    keyring: Option<Keyring>,
+    concurrency: usize,
 }
 
 impl S3Storage {
-    pub async fn new(bucket: Option<String>) -> Result<Self> {
+    pub async fn new(bucket: String) -> Result<Self> {
         let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
         let client = Client::new(&config);
-        let bucket = bucket
-            .or_else(|| std::env::var("GROOVEPUSH_BUCKET").ok())
-            .unwrap_or_else(|| DEFAULT_BUCKET.to_string());
+        let multipart_threshold = std::env::var("GROOVEPUSH_MULTIPART_THRESHOLD_MB")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(|mb| mb * 1024 * 1024)
+            .unwrap_or(DEFAULT_MULTIPART_THRESHOLD);
+
+        Ok(Self {
+            client,
+            bucket,
+            multipart_threshold,
+            keyring: None,
+            concurrency: DEFAULT_CONCURRENCY,
+        })
+    }
 
-        Ok(Self { client, bucket })
+    /// blob・メタデータの暗号化に使う鍵を設定する
+    pub fn with_keyring(mut self, keyring: Option<Keyring>) -> Self {
+        self.keyring = keyring;
+        self
     }
 
-    pub async fn get_remote_state(
-        &self,
-        project_name: &str,
-    ) -> Result<HashMap<String, String>> {
+    /// 同時に転送するblob数を設定する
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    fn location(&self, project_name: &str) -> String {
+        format!("s3://{}/{}/", self.bucket, project_name)
+    }
+
+    fn keyring(&self) -> Option<&Keyring> {
+        self.keyring.as_ref()
+    }
+
+    fn concurrency(&self) -> usize {
+        self.concurrency
+    }
+
+    async fn get_remote_state(&self, project_name: &str) -> Result<HashMap<String, String>> {
         let key = format!("{}/.gp/current_state.json", project_name);
 
         let result = self
@@ -50,12 +103,11 @@ impl S3Storage {
                     .collect()
                     .await
                     .map_err(|e| GpError::S3Error(e.to_string()))?;
-                let bytes = body.into_bytes();
-                let content = String::from_utf8_lossy(&bytes);
-                serde_json::from_str(&content).map_err(|e| GpError::S3Error(e.to_string()))
+                let bytes = decode_bytes(&body.into_bytes(), self.keyring())?;
+                serde_json::from_slice(&bytes).map_err(|e| GpError::S3Error(e.to_string()))
             }
             Err(e) => {
-                if e.as_service_error().map_or(false, |svc| svc.is_no_such_key()) {
+                if e.as_service_error().is_some_and(|svc| svc.is_no_such_key()) {
                     Ok(HashMap::new())
                 } else {
                     Err(GpError::S3Error(e.to_string()))
@@ -64,30 +116,16 @@ impl S3Storage {
         }
     }
 
-    pub async fn save_state(
-        &self,
-        project_name: &str,
-        files: &[ScannedFile],
-    ) -> Result<()> {
-        let state: HashMap<String, String> = files
-            .iter()
-            .map(|f| {
-                (
-                    f.relative_path.to_string_lossy().to_string(),
-                    f.hash.clone(),
-                )
-            })
-            .collect();
-
+    async fn save_state(&self, project_name: &str, state: &HashMap<String, String>) -> Result<()> {
         let key = format!("{}/.gp/current_state.json", project_name);
-        let body = serde_json::to_string_pretty(&state)
-            .map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body = serde_json::to_vec_pretty(state).map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body = encode_bytes(&body, self.keyring())?;
 
         self.client
             .put_object()
             .bucket(&self.bucket)
             .key(&key)
-            .body(ByteStream::from(body.into_bytes()))
+            .body(ByteStream::from(body))
             .send()
             .await
             .map_err(|e| GpError::S3Error(e.to_string()))?;
@@ -95,36 +133,30 @@ impl S3Storage {
         Ok(())
     }
 
-    pub fn bucket(&self) -> &str {
-        &self.bucket
-    }
-
-    pub async fn upload_blobs(
+    async fn upload_blobs(
         &self,
         project_name: &str,
-        files: &[ScannedFile],
+        blobs: &[BlobUpload],
+        encoding: &BlobEncoding,
+        journal: &UploadJournal,
     ) -> Result<usize> {
-        if files.is_empty() {
+        if blobs.is_empty() {
             return Ok(0);
         }
 
-        let pb = ProgressBar::new(files.len() as u64);
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} blobs")
-                .expect("プログレスバーのテンプレートエラー")
-                .progress_chars("#>-"),
-        );
+        let pb = blob_progress_bar(blobs.len() as u64);
 
-        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_UPLOADS));
+        let semaphore = Arc::new(Semaphore::new(self.concurrency));
         let mut handles = Vec::new();
 
-        for file in files {
+        for blob in blobs {
             let client = self.client.clone();
             let bucket = self.bucket.clone();
             let project = project_name.to_string();
-            let hash = file.hash.clone();
-            let absolute_path = file.absolute_path.clone();
+            let blob = blob.clone();
+            let multipart_threshold = self.multipart_threshold;
+            let encoding = encoding.clone();
+            let journal = journal.clone();
             let pb = pb.clone();
             let sem = semaphore.clone();
 
@@ -132,30 +164,64 @@ impl S3Storage {
                 let _permit =
                     sem.acquire().await.map_err(|e| std::io::Error::other(e.to_string()))?;
 
-                let key = format!("{}/.gp/blobs/{}", project, hash);
+                if journal.is_completed(&blob.hash) {
+                    pb.inc(1);
+                    return Ok::<bool, std::io::Error>(false);
+                }
+
+                let key = format!("{}/.gp/blobs/{}", project, blob.hash);
 
-                let exists = client
+                // 古いblobは送り直し、gcの猶予期間内に収める
+                let reusable = client
                     .head_object()
                     .bucket(&bucket)
                     .key(&key)
                     .send()
                     .await
-                    .is_ok();
-
-                if exists {
+                    .is_ok_and(|head| {
+                        reusable_blob(head.last_modified().and_then(|t| {
+                            DateTime::<Utc>::from_timestamp(t.secs(), t.subsec_nanos())
+                        }))
+                    });
+
+                if reusable {
+                    journal
+                        .mark_completed(&blob.hash)
+                        .await
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
                     pb.inc(1);
-                    return Ok::<bool, std::io::Error>(false);
+                    return Ok(false);
                 }
 
-                let body = fs::read(&absolute_path).await?;
-                let stream = ByteStream::from(body);
+                let staged = encoding
+                    .stage(&blob)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+                let blob = &staged.upload;
+
+                if use_multipart(blob.size, multipart_threshold) {
+                    upload_multipart(&client, &bucket, &key, &staged, &journal).await?;
+                } else {
+                    let stream = ByteStream::read_from()
+                        .path(&blob.path)
+                        .offset(blob.offset)
+                        .length(Length::Exact(blob.size))
+                        .build()
+                        .await
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                    client
+                        .put_object()
+                        .bucket(&bucket)
+                        .key(&key)
+                        .body(stream)
+                        .send()
+                        .await
+                        .map_err(|e| std::io::Error::other(e.to_string()))?;
+                }
 
-                client
-                    .put_object()
-                    .bucket(&bucket)
-                    .key(&key)
-                    .body(stream)
-                    .send()
+                journal
+                    .mark_completed(&blob.hash)
                     .await
                     .map_err(|e| std::io::Error::other(e.to_string()))?;
 
@@ -182,32 +248,248 @@ impl S3Storage {
         Ok(uploaded_count)
     }
 
-    pub async fn download_blob(
+    async fn get_lock(&self, project_name: &str) -> Result<Option<ProjectLock>> {
+        let key = format!("{}/.gp/lock.json", project_name);
+
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => {
+                let body = output
+                    .body
+                    .collect()
+                    .await
+                    .map_err(|e| GpError::S3Error(e.to_string()))?;
+                let bytes = decode_bytes(&body.into_bytes(), self.keyring())?;
+                serde_json::from_slice(&bytes)
+                    .map(Some)
+                    .map_err(|e| GpError::S3Error(e.to_string()))
+            }
+            Err(e) => {
+                if e.as_service_error().is_some_and(|svc| svc.is_no_such_key()) {
+                    Ok(None)
+                } else {
+                    Err(GpError::S3Error(e.to_string()))
+                }
+            }
+        }
+    }
+
+    /// If-None-Match: * で、既にロックがあれば書き込まない
+    async fn create_lock(&self, project_name: &str, lock: &ProjectLock) -> Result<()> {
+        let key = format!("{}/.gp/lock.json", project_name);
+        let body = serde_json::to_vec_pretty(lock).map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body = encode_bytes(&body, self.keyring())?;
+
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from(body))
+            .if_none_match("*")
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e)
+                if matches!(
+                    e.raw_response().map(|r| r.status().as_u16()),
+                    Some(412) | Some(409)
+                ) =>
+            {
+                let holder = self.get_lock(project_name).await?;
+                Err(GpError::ProjectLocked(
+                    holder.map(|l| l.to_string()).unwrap_or_default(),
+                ))
+            }
+            Err(e) => Err(GpError::S3Error(e.to_string())),
+        }
+    }
+
+    /// 読み込んだロックのETagを If-Match に指定し、その間に変わっていれば書き込まない
+    async fn replace_lock(
         &self,
         project_name: &str,
-        hash: &str,
-    ) -> Result<Vec<u8>> {
-        let key = format!("{}/.gp/blobs/{}", project_name, hash);
+        expected: &ProjectLock,
+        lock: &ProjectLock,
+    ) -> Result<()> {
+        let key = format!("{}/.gp/lock.json", project_name);
 
-        let output = self
+        let output = match self
             .client
             .get_object()
             .bucket(&self.bucket)
             .key(&key)
             .send()
             .await
-            .map_err(|e| GpError::BlobNotFound(format!("{}: {}", hash, e)))?;
-
+        {
+            Ok(output) => output,
+            Err(e) if e.as_service_error().is_some_and(|svc| svc.is_no_such_key()) => {
+                return self.create_lock(project_name, lock).await;
+            }
+            Err(e) => return Err(GpError::S3Error(e.to_string())),
+        };
+        let e_tag = output
+            .e_tag()
+            .ok_or_else(|| GpError::S3Error("ロックのETagが返されませんでした".to_string()))?
+            .to_string();
         let body = output
             .body
             .collect()
             .await
             .map_err(|e| GpError::S3Error(e.to_string()))?;
+        let bytes = decode_bytes(&body.into_bytes(), self.keyring())?;
+        let current: ProjectLock =
+            serde_json::from_slice(&bytes).map_err(|e| GpError::S3Error(e.to_string()))?;
+        if current != *expected {
+            return Err(GpError::ProjectLocked(current.to_string()));
+        }
+
+        let body = serde_json::to_vec_pretty(lock).map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body = encode_bytes(&body, self.keyring())?;
+
+        let result = self
+            .client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .body(ByteStream::from(body))
+            .if_match(e_tag)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e)
+                if matches!(
+                    e.raw_response().map(|r| r.status().as_u16()),
+                    Some(412) | Some(409) | Some(404)
+                ) =>
+            {
+                match self.get_lock(project_name).await? {
+                    Some(holder) => Err(GpError::ProjectLocked(holder.to_string())),
+                    None => self.create_lock(project_name, lock).await,
+                }
+            }
+            Err(e) => Err(GpError::S3Error(e.to_string())),
+        }
+    }
+
+    async fn delete_lock(&self, project_name: &str) -> Result<()> {
+        let key = format!("{}/.gp/lock.json", project_name);
+
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| GpError::S3Error(e.to_string()))?;
+
+        Ok(())
+    }
+
+    async fn list_blobs(&self, project_name: &str) -> Result<Vec<StoredBlob>> {
+        let prefix = format!("{}/.gp/blobs/", project_name);
+        let mut pages = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .prefix(&prefix)
+            .into_paginator()
+            .send();
+
+        let mut blobs = Vec::new();
+        while let Some(page) = pages.next().await {
+            let page = page.map_err(|e| GpError::S3Error(e.to_string()))?;
+            for object in page.contents() {
+                let Some(hash) = object.key().and_then(|k| k.strip_prefix(&prefix)) else {
+                    continue;
+                };
+                blobs.push(StoredBlob {
+                    hash: hash.to_string(),
+                    size: object.size().unwrap_or(0) as u64,
+                    last_modified: object
+                        .last_modified()
+                        .and_then(|t| DateTime::<Utc>::from_timestamp(t.secs(), t.subsec_nanos())),
+                });
+            }
+        }
+
+        Ok(blobs)
+    }
+
+    async fn delete_blobs(&self, project_name: &str, hashes: &[String]) -> Result<()> {
+        for batch in hashes.chunks(MAX_DELETE_BATCH) {
+            let objects = batch
+                .iter()
+                .map(|hash| {
+                    ObjectIdentifier::builder()
+                        .key(format!("{}/.gp/blobs/{}", project_name, hash))
+                        .build()
+                        .map_err(|e| GpError::S3Error(e.to_string()))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let delete = Delete::builder()
+                .set_objects(Some(objects))
+                .quiet(true)
+                .build()
+                .map_err(|e| GpError::S3Error(e.to_string()))?;
+
+            let output = self
+                .client
+                .delete_objects()
+                .bucket(&self.bucket)
+                .delete(delete)
+                .send()
+                .await
+                .map_err(|e| GpError::S3Error(e.to_string()))?;
+
+            if let Some(error) = output.errors().first() {
+                return Err(GpError::S3Error(format!(
+                    "{}: {}",
+                    error.key().unwrap_or_default(),
+                    error.message().unwrap_or_default()
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn read_blob(&self, project_name: &str, hash: &str, sink: &mut BlobSink) -> Result<()> {
+        let key = format!("{}/.gp/blobs/{}", project_name, hash);
+
+        let mut output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(&key)
+            .send()
+            .await
+            .map_err(|e| GpError::BlobNotFound(format!("{}: {}", hash, e)))?;
+
+        while let Some(chunk) = output
+            .body
+            .try_next()
+            .await
+            .map_err(|e| GpError::S3Error(e.to_string()))?
+        {
+            sink.write(&chunk).await?;
+        }
 
-        Ok(body.into_bytes().to_vec())
+        Ok(())
     }
 
-    pub async fn get_history(&self, project_name: &str) -> Result<Option<History>> {
+    async fn load_history(&self, project_name: &str) -> Result<(Option<History>, HistoryVersion)> {
         let key = format!("{}/.gp/history.json", project_name);
 
         let result = self
@@ -220,20 +502,23 @@ impl S3Storage {
 
         match result {
             Ok(output) => {
+                let version = output
+                    .e_tag()
+                    .map(|e_tag| HistoryVersion::Tag(e_tag.to_string()))
+                    .ok_or_else(|| GpError::S3Error("ETagが返されませんでした".to_string()))?;
                 let body = output
                     .body
                     .collect()
                     .await
                     .map_err(|e| GpError::S3Error(e.to_string()))?;
-                let bytes = body.into_bytes();
-                let content = String::from_utf8_lossy(&bytes);
-                let history: History = serde_json::from_str(&content)
-                    .map_err(|e| GpError::S3Error(e.to_string()))?;
-                Ok(Some(history))
+                let bytes = decode_bytes(&body.into_bytes(), self.keyring())?;
+                let history: History =
+                    serde_json::from_slice(&bytes).map_err(|e| GpError::S3Error(e.to_string()))?;
+                Ok((Some(history), version))
             }
             Err(e) => {
-                if e.as_service_error().map_or(false, |svc| svc.is_no_such_key()) {
-                    Ok(None)
+                if e.as_service_error().is_some_and(|svc| svc.is_no_such_key()) {
+                    Ok((None, HistoryVersion::Missing))
                 } else {
                     Err(GpError::S3Error(e.to_string()))
                 }
@@ -241,27 +526,323 @@ impl S3Storage {
         }
     }
 
-    pub async fn save_history(&self, project_name: &str, history: &History) -> Result<()> {
+    /// If-Match / If-None-Match による条件付きPUTで、読み込み後に更新されていたら書き込まない
+    async fn save_history_if(
+        &self,
+        project_name: &str,
+        history: &History,
+        expected: &HistoryVersion,
+    ) -> Result<()> {
         let key = format!("{}/.gp/history.json", project_name);
-        let body = serde_json::to_string_pretty(history)
-            .map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body =
+            serde_json::to_vec_pretty(history).map_err(|e| GpError::S3Error(e.to_string()))?;
+        let body = encode_bytes(&body, self.keyring())?;
 
-        self.client
+        let request = self
+            .client
             .put_object()
             .bucket(&self.bucket)
             .key(&key)
-            .body(ByteStream::from(body.into_bytes()))
+            .body(ByteStream::from(body));
+        let request = match expected {
+            HistoryVersion::Missing => request.if_none_match("*"),
+            HistoryVersion::Tag(e_tag) => request.if_match(e_tag),
+        };
+
+        request.send().await.map_err(|e| {
+            // 412: 条件不成立、409: 同じキーへの条件付き書き込みが同時に発生
+            match e.raw_response().map(|r| r.status().as_u16()) {
+                Some(412) | Some(409) => GpError::HistoryConflict,
+                _ => GpError::S3Error(e.to_string()),
+            }
+        })?;
+
+        Ok(())
+    }
+}
+
+/// ファイルをディスクから直接ストリームしつつマルチパートでアップロードする
+///
+/// 進行状況は `journal` に記録し、中断後の次回プッシュでは送信済みのパートを飛ばして再開する。
+async fn upload_multipart(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    staged: &StagedBlob,
+    journal: &UploadJournal,
+) -> std::io::Result<()> {
+    let blob = &staged.upload;
+    let hash = &blob.hash;
+    let state = match resumable_upload(client, bucket, key, staged, journal).await {
+        Some(state) => state,
+        None => {
+            let upload_id = client
+                .create_multipart_upload()
+                .bucket(bucket)
+                .key(key)
+                .send()
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?
+                .upload_id
+                .ok_or_else(|| std::io::Error::other("upload_idが返されませんでした"))?;
+            let part_size = part_size_for(blob.size);
+            journal
+                .start_multipart(hash, &upload_id, part_size, &staged.form)
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            MultipartState {
+                upload_id,
+                part_size,
+                form: staged.form.clone(),
+                ..Default::default()
+            }
+        }
+    };
+
+    let upload = MultipartUpload {
+        client,
+        bucket,
+        key,
+        blob,
+        journal,
+    };
+    let parts = upload.upload_parts(&state).await?;
+
+    client
+        .complete_multipart_upload()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(&state.upload_id)
+        .multipart_upload(
+            CompletedMultipartUpload::builder()
+                .set_parts(Some(parts))
+                .build(),
+        )
+        .send()
+        .await
+        .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+    Ok(())
+}
+
+/// 記録済みのアップロードがS3上でまだ有効なら再開に使う
+///
+/// 前回と今回でエンコード後の形式（サイズ・圧縮方式・鍵）が違えば、送信済みのパートは
+/// 今回の内容の一部ではないため、アップロードを中止して最初から送り直す。
+async fn resumable_upload(
+    client: &Client,
+    bucket: &str,
+    key: &str,
+    staged: &StagedBlob,
+    journal: &UploadJournal,
+) -> Option<MultipartState> {
+    let hash = &staged.upload.hash;
+    let state = journal.multipart(hash)?;
+
+    if state.form != staged.form {
+        let _ = client
+            .abort_multipart_upload()
+            .bucket(bucket)
+            .key(key)
+            .upload_id(&state.upload_id)
             .send()
+            .await;
+        let _ = journal.discard_multipart(hash).await;
+        return None;
+    }
+
+    let alive = client
+        .list_parts()
+        .bucket(bucket)
+        .key(key)
+        .upload_id(&state.upload_id)
+        .max_parts(1)
+        .send()
+        .await
+        .is_ok();
+
+    if alive {
+        Some(state)
+    } else {
+        let _ = journal.discard_multipart(hash).await;
+        None
+    }
+}
+
+struct MultipartUpload<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    key: &'a str,
+    blob: &'a BlobUpload,
+    journal: &'a UploadJournal,
+}
+
+impl MultipartUpload<'_> {
+    async fn upload_parts(&self, state: &MultipartState) -> std::io::Result<Vec<CompletedPart>> {
+        let semaphore = Arc::new(Semaphore::new(MAX_CONCURRENT_PARTS));
+        let mut handles = Vec::new();
+
+        for (part_number, offset, length) in part_ranges(self.blob.size, state.part_size) {
+            if let Some(e_tag) = state.parts.get(&part_number) {
+                let e_tag = e_tag.clone();
+                handles.push(tokio::spawn(async move {
+                    Ok::<(i32, String), std::io::Error>((part_number, e_tag))
+                }));
+                continue;
+            }
+
+            let client = self.client.clone();
+            let bucket = self.bucket.to_string();
+            let key = self.key.to_string();
+            let hash = self.blob.hash.clone();
+            let upload_id = state.upload_id.clone();
+            let path = self.blob.path.clone();
+            let offset = self.blob.offset + offset;
+            let journal = self.journal.clone();
+            let sem = semaphore.clone();
+
+            handles.push(tokio::spawn(async move {
+                let _permit =
+                    sem.acquire().await.map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                let part = PartUpload {
+                    client: &client,
+                    bucket: &bucket,
+                    key: &key,
+                    upload_id: &upload_id,
+                    path,
+                    part_number,
+                    offset,
+                    length,
+                };
+                let e_tag = part.send_with_retry().await?;
+
+                journal
+                    .record_part(&hash, part_number, &e_tag)
+                    .await
+                    .map_err(|e| std::io::Error::other(e.to_string()))?;
+
+                Ok((part_number, e_tag))
+            }));
+        }
+
+        let mut parts = Vec::with_capacity(handles.len());
+        for handle in handles {
+            let (part_number, e_tag) = handle
+                .await
+                .map_err(|e| std::io::Error::other(e.to_string()))??;
+            parts.push(
+                CompletedPart::builder()
+                    .part_number(part_number)
+                    .e_tag(e_tag)
+                    .build(),
+            );
+        }
+
+        Ok(parts)
+    }
+}
+
+struct PartUpload<'a> {
+    client: &'a Client,
+    bucket: &'a str,
+    key: &'a str,
+    upload_id: &'a str,
+    path: PathBuf,
+    part_number: i32,
+    offset: u64,
+    length: u64,
+}
+
+impl PartUpload<'_> {
+    /// 1パートを送信し、失敗時は指数バックオフで再試行する
+    async fn send_with_retry(&self) -> std::io::Result<String> {
+        let mut attempt = 1;
+
+        loop {
+            match self.send().await {
+                Ok(e_tag) => return Ok(e_tag),
+                Err(e) if attempt >= MAX_PART_ATTEMPTS => return Err(e),
+                Err(_) => {
+                    tokio::time::sleep(PART_RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn send(&self) -> std::io::Result<String> {
+        let body = ByteStream::read_from()
+            .path(&self.path)
+            .offset(self.offset)
+            .length(Length::Exact(self.length))
+            .build()
             .await
-            .map_err(|e| GpError::S3Error(e.to_string()))?;
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
 
-        Ok(())
+        self.client
+            .upload_part()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(self.upload_id)
+            .part_number(self.part_number)
+            .content_length(self.length as i64)
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| std::io::Error::other(e.to_string()))?
+            .e_tag
+            .ok_or_else(|| std::io::Error::other("ETagが返されませんでした"))
     }
 }
 
-pub fn extract_project_name(path: &Path) -> String {
-    path.file_name()
-        .and_then(|n| n.to_str())
-        .unwrap_or("unnamed_project")
-        .to_string()
+/// 空のblobはパートが0個になり完了できないため、閾値によらず単一PUTで送る
+fn use_multipart(size: u64, threshold: u64) -> bool {
+    size > 0 && size >= threshold
+}
+
+/// パート数が上限を超えないようにパートサイズを決める
+fn part_size_for(size: u64) -> u64 {
+    MIN_PART_SIZE.max(size.div_ceil(MAX_PARTS))
+}
+
+/// `(パート番号, オフセット, 長さ)` の一覧。パート番号は1始まり
+fn part_ranges(size: u64, part_size: u64) -> Vec<(i32, u64, u64)> {
+    (0..size.div_ceil(part_size))
+        .map(|i| {
+            let offset = i * part_size;
+            (i as i32 + 1, offset, part_size.min(size - offset))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_part_ranges_cover_file() {
+        let parts = part_ranges(10, 4);
+        assert_eq!(parts, vec![(1, 0, 4), (2, 4, 4), (3, 8, 2)]);
+
+        let parts = part_ranges(8, 4);
+        assert_eq!(parts, vec![(1, 0, 4), (2, 4, 4)]);
+    }
+
+    #[test]
+    fn test_empty_blob_never_uses_multipart() {
+        assert!(!use_multipart(0, 0));
+        assert!(use_multipart(1, 0));
+        assert!(!use_multipart(10, 11));
+        assert!(use_multipart(11, 11));
+    }
+
+    #[test]
+    fn test_part_size_respects_part_limit() {
+        assert_eq!(part_size_for(100 * 1024 * 1024), MIN_PART_SIZE);
+
+        let huge = 500 * 1024 * 1024 * 1024;
+        let part_size = part_size_for(huge);
+        assert!(part_ranges(huge, part_size).len() as u64 <= MAX_PARTS);
+    }
 }
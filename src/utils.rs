@@ -1,5 +1,7 @@
 use anyhow::Result;
 
+use crate::storage::Snapshot;
+
 pub fn format_size(bytes: u64) -> String {
     const KB: u64 = 1024;
     const MB: u64 = KB * 1024;
@@ -26,6 +28,29 @@ pub fn validate_project_name(name: &str) -> Result<()> {
     Ok(())
 }
 
+/// タグ名・ブランチ名の検証。空白や区切り文字を含む名前はコマンドラインで扱いにくいため拒否する
+///
+/// スナップショットIDの先頭部分と読める名前も、IDでの指定を横取りしてしまうため拒否する。
+pub fn validate_ref_name(name: &str) -> Result<()> {
+    if name.is_empty() {
+        anyhow::bail!("名前は空にできません");
+    }
+    if Snapshot::looks_like_id(name) {
+        anyhow::bail!(
+            "スナップショットIDと紛らわしい名前は使えません: '{}'（数字以外で始めてください）",
+            name
+        );
+    }
+    if name.starts_with('-')
+        || name
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '/' || c == '\\')
+    {
+        anyhow::bail!("無効な名前: '{}'", name);
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -69,4 +94,17 @@ mod tests {
         assert!(validate_project_name("foo/bar").is_err());
         assert!(validate_project_name("foo\\bar").is_err());
     }
+
+    #[test]
+    fn test_validate_ref_name() {
+        assert!(validate_ref_name("mix-v3").is_ok());
+        assert!(validate_ref_name("sent-to-label").is_ok());
+        assert!(validate_ref_name("").is_err());
+        assert!(validate_ref_name("final mix").is_err());
+        assert!(validate_ref_name("--force").is_err());
+        assert!(validate_ref_name("a/b").is_err());
+        assert!(validate_ref_name("2026").is_err());
+        assert!(validate_ref_name("20260203T14").is_err());
+        assert!(validate_ref_name("2nd-mix").is_ok());
+    }
 }
@@ -0,0 +1,334 @@
+use crate::error::{GpError, Result};
+use crate::storage::{History, Snapshot, DEFAULT_BRANCH};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const WORKSPACE_FILE: &str = "workspace.json";
+const TRASH_DIR: &str = "trash";
+
+/// 作業コピーの同期状態（`.gp/workspace.json`）
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Workspace {
+    /// 作業コピーの元になったスナップショット（最後にpush・pull・clone・checkoutしたもの）
+    #[serde(default)]
+    pub base: Option<String>,
+    /// 作業中のブランチ（`None` は `main`）
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+}
+
+impl Workspace {
+    fn path(gp_dir: &Path) -> PathBuf {
+        gp_dir.join(WORKSPACE_FILE)
+    }
+
+    /// 読み込む。ファイルが無ければ空の状態
+    pub fn load(gp_dir: &Path) -> Result<Self> {
+        match fs::read(Self::path(gp_dir)) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .map_err(|e| GpError::StorageError(format!("{}: {}", WORKSPACE_FILE, e))),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    pub fn save(&self, gp_dir: &Path) -> Result<()> {
+        fs::create_dir_all(gp_dir)?;
+        let body =
+            serde_json::to_vec_pretty(self).map_err(|e| GpError::StorageError(e.to_string()))?;
+        let path = Self::path(gp_dir);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    /// 作業中のブランチ名
+    pub fn branch(&self) -> &str {
+        self.branch.as_deref().unwrap_or(DEFAULT_BRANCH)
+    }
+
+    /// 元になったスナップショット。記録が無いか、履歴から削除されていれば `None`
+    pub fn base_snapshot<'h>(&self, history: &'h History) -> Option<&'h Snapshot> {
+        let base = self.base.as_deref()?;
+        history.snapshots.iter().find(|s| s.id == base)
+    }
+
+    /// 元になったスナップショットを記録する
+    pub fn record_base(gp_dir: &Path, snapshot_id: &str) -> Result<()> {
+        let mut workspace = Self::load(gp_dir)?;
+        workspace.base = Some(snapshot_id.to_string());
+        workspace.save(gp_dir)
+    }
+}
+
+/// 作業コピーを最新スナップショットに追いつかせるための操作
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PullPlan {
+    /// リモートの内容をダウンロードするファイル
+    pub download: BTreeSet<String>,
+    /// リモートで削除されたため削除するファイル
+    pub delete: BTreeSet<String>,
+    /// ローカルでも編集されていて、適用すると上書きされるファイル
+    pub conflicts: BTreeSet<String>,
+}
+
+impl PullPlan {
+    /// ローカルのファイル（パス → ハッシュ）と最新スナップショットを比べる
+    ///
+    /// `base` は作業コピーの元になったスナップショット。元から変わったファイルだけを
+    /// リモートの変更として適用し、ローカルだけの変更はそのまま残す。
+    /// `base` が分からない場合、ローカルにあってリモートと異なるファイルはすべて競合として扱う
+    /// （ローカルだけにあるファイルは削除しない）。
+    pub fn new(local: &HashMap<String, String>, head: &Snapshot, base: Option<&Snapshot>) -> Self {
+        let mut plan = Self::default();
+        let paths: BTreeSet<&String> = local.keys().chain(head.files.keys()).collect();
+
+        for path in paths {
+            let local_hash = local.get(path);
+            let remote_hash = head.files.get(path);
+            if local_hash == remote_hash {
+                continue;
+            }
+
+            let base_hash = match base {
+                Some(base) => base.files.get(path),
+                // 元が分からなければ、ローカルに無いファイルの取得だけを安全とみなす
+                None if local_hash.is_none() => None,
+                None if remote_hash.is_none() => continue,
+                None => {
+                    plan.conflicts.insert(path.clone());
+                    continue;
+                }
+            };
+
+            // リモートで変わっていなければ、ローカルの変更を残す
+            if remote_hash == base_hash {
+                continue;
+            }
+
+            if local_hash != base_hash {
+                plan.conflicts.insert(path.clone());
+            } else if remote_hash.is_some() {
+                plan.download.insert(path.clone());
+            } else {
+                plan.delete.insert(path.clone());
+            }
+        }
+
+        plan
+    }
+
+    /// 競合しているファイルもリモートの内容で上書きする
+    pub fn force(&mut self, head: &Snapshot) {
+        for path in std::mem::take(&mut self.conflicts) {
+            if head.files.contains_key(&path) {
+                self.download.insert(path);
+            } else {
+                self.delete.insert(path);
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.download.is_empty() && self.delete.is_empty() && self.conflicts.is_empty()
+    }
+}
+
+/// 作業コピーを指定したスナップショットの内容に置き換えるための操作
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CheckoutPlan {
+    /// 内容が異なるか存在しないため、ダウンロードするファイル
+    pub download: BTreeSet<String>,
+    /// スナップショットに含まれないため削除するファイル
+    pub delete: BTreeSet<String>,
+    /// 上書き・削除されるファイルのうち、`base` から変更されていて保存されていないもの
+    pub unsaved: BTreeSet<String>,
+}
+
+impl CheckoutPlan {
+    /// ローカルのファイル（パス → ハッシュ）と復元するスナップショットを比べる
+    ///
+    /// `base` は作業コピーの元になったスナップショット。`base` と同じ内容のファイルは
+    /// リモートに保存済みなので、上書き・削除しても失われない。
+    pub fn new(
+        local: &HashMap<String, String>,
+        target: &Snapshot,
+        base: Option<&Snapshot>,
+    ) -> Self {
+        let mut plan = Self::default();
+
+        for (path, hash) in &target.files {
+            if local.get(path) != Some(hash) {
+                plan.download.insert(path.clone());
+            }
+        }
+        for path in local.keys() {
+            if !target.files.contains_key(path) {
+                plan.delete.insert(path.clone());
+            }
+        }
+
+        for path in plan.download.iter().chain(&plan.delete) {
+            let Some(local_hash) = local.get(path) else {
+                continue;
+            };
+            if base.and_then(|b| b.files.get(path)) != Some(local_hash) {
+                plan.unsaved.insert(path.clone());
+            }
+        }
+
+        plan
+    }
+}
+
+/// 作業コピーのファイルを削除し、空になった親ディレクトリも `root` の手前まで削除する
+pub fn remove_file(root: &Path, relative_path: &str) -> Result<()> {
+    let file = root.join(relative_path);
+    match fs::remove_file(&file) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    for dir in file.ancestors().skip(1) {
+        if dir == root || fs::remove_dir(dir).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// 作業コピーのファイルを `.gp/trash/{label}/` 以下の同じ相対パスに移動し、移動先を返す
+pub fn move_to_trash(root: &Path, relative_path: &str, label: &str) -> Result<PathBuf> {
+    let dest = root
+        .join(".gp")
+        .join(TRASH_DIR)
+        .join(label)
+        .join(relative_path);
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(root.join(relative_path), &dest)?;
+    remove_file(root, relative_path)?;
+    Ok(dest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(files: &[(&str, &str)]) -> Snapshot {
+        let files = files
+            .iter()
+            .map(|(p, h)| (p.to_string(), h.to_string()))
+            .collect();
+        Snapshot::new(None, files, HashMap::new(), HashMap::new(), None, 0)
+    }
+
+    fn local(files: &[(&str, &str)]) -> HashMap<String, String> {
+        snapshot(files).files
+    }
+
+    fn set(paths: &[&str]) -> BTreeSet<String> {
+        paths.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn test_workspace_roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Workspace::load(dir.path()).unwrap().base.is_none());
+
+        Workspace::record_base(dir.path(), "20260203T143052.000Z").unwrap();
+        let mut workspace = Workspace::load(dir.path()).unwrap();
+        assert_eq!(workspace.base.as_deref(), Some("20260203T143052.000Z"));
+        assert_eq!(workspace.branch(), "main");
+
+        workspace.branch = Some("remix".to_string());
+        workspace.save(dir.path()).unwrap();
+        Workspace::record_base(dir.path(), "20260204T090000.000Z").unwrap();
+        assert_eq!(Workspace::load(dir.path()).unwrap().branch(), "remix");
+    }
+
+    #[test]
+    fn test_pull_applies_remote_changes_and_keeps_local_ones() {
+        let base = snapshot(&[
+            ("a.als", "a1"),
+            ("b.wav", "b1"),
+            ("c.wav", "c1"),
+            ("d.als", "d1"),
+        ]);
+        let head = snapshot(&[
+            ("a.als", "a2"),
+            ("b.wav", "b1"),
+            ("d.als", "d2"),
+            ("e.wav", "e1"),
+        ]);
+        let local = local(&[
+            ("a.als", "a1"),   // リモートだけ変更 → ダウンロード
+            ("b.wav", "b9"),   // ローカルだけ変更 → 残す
+            ("c.wav", "c1"),   // リモートで削除 → 削除
+            ("d.als", "d9"),   // 両方で変更 → 競合
+            ("new.als", "n1"), // ローカルだけに追加 → 残す
+        ]);
+
+        let mut plan = PullPlan::new(&local, &head, Some(&base));
+        assert_eq!(plan.download, set(&["a.als", "e.wav"]));
+        assert_eq!(plan.delete, set(&["c.wav"]));
+        assert_eq!(plan.conflicts, set(&["d.als"]));
+
+        plan.force(&head);
+        assert_eq!(plan.download, set(&["a.als", "d.als", "e.wav"]));
+        assert!(plan.conflicts.is_empty());
+    }
+
+    #[test]
+    fn test_checkout_detects_unsaved_changes() {
+        let base = snapshot(&[("a.als", "a1"), ("b.wav", "b1"), ("c.wav", "c1")]);
+        let target = snapshot(&[("a.als", "a0"), ("b.wav", "b1"), ("d.wav", "d1")]);
+        let local = local(&[
+            ("a.als", "a9"),   // 編集済みで上書きされる → 未保存
+            ("b.wav", "b1"),   // 同じ内容 → 何もしない
+            ("c.wav", "c1"),   // 保存済みで削除される
+            ("new.als", "n1"), // 未保存のまま削除される
+        ]);
+
+        let plan = CheckoutPlan::new(&local, &target, Some(&base));
+        assert_eq!(plan.download, set(&["a.als", "d.wav"]));
+        assert_eq!(plan.delete, set(&["c.wav", "new.als"]));
+        assert_eq!(plan.unsaved, set(&["a.als", "new.als"]));
+
+        let plan = CheckoutPlan::new(&HashMap::new(), &target, None);
+        assert_eq!(plan.download, set(&["a.als", "b.wav", "d.wav"]));
+        assert!(plan.unsaved.is_empty());
+    }
+
+    #[test]
+    fn test_move_to_trash_keeps_relative_path() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("Samples")).unwrap();
+        fs::write(dir.path().join("Samples/kick.wav"), b"kick").unwrap();
+
+        let dest = move_to_trash(dir.path(), "Samples/kick.wav", "20260203T143052Z").unwrap();
+        assert_eq!(
+            dest,
+            dir.path()
+                .join(".gp/trash/20260203T143052Z/Samples/kick.wav")
+        );
+        assert_eq!(fs::read(&dest).unwrap(), b"kick");
+        assert!(!dir.path().join("Samples").exists());
+    }
+
+    #[test]
+    fn test_pull_without_base_is_conservative() {
+        let head = snapshot(&[("a.als", "a2"), ("e.wav", "e1")]);
+        let local = local(&[("a.als", "a1"), ("new.als", "n1")]);
+
+        let plan = PullPlan::new(&local, &head, None);
+        assert_eq!(plan.download, set(&["e.wav"]));
+        assert!(plan.delete.is_empty());
+        assert_eq!(plan.conflicts, set(&["a.als"]));
+    }
+}